@@ -1,34 +1,114 @@
-use num::rational::BigRational;
+use binding_map::Binding;
+use number::Number;
 use regex::Regex;
 use std::cmp::Ordering;
+use std::collections::BTreeSet;
 use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+// Byte offsets in the source a node was parsed from. As tokens consume the
+// whitespace that follows them, the end of a span can include some. Spans
+// don't take part in comparisons, so that nodes parsed from different sources
+// can be compared structurally.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Span {
+        Span {
+            start,
+            end,
+        }
+    }
+
+    pub fn to(&self, other: Span) -> Span {
+        Span::new(self.start, other.end)
+    }
+}
+
+impl PartialEq for Span {
+    fn eq(&self, _: &Span) -> bool {
+        true
+    }
+}
+
+impl Eq for Span {}
+
+impl Ord for Span {
+    fn cmp(&self, _: &Span) -> Ordering {
+        Ordering::Equal
+    }
+}
+
+impl PartialOrd for Span {
+    fn partial_cmp(&self, other: &Span) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Hash for Span {
+    fn hash<H: Hasher>(&self, _: &mut H) {}
+}
 
 #[derive(Clone, Eq, Debug, Hash, Ord, PartialEq, PartialOrd)]
 pub enum Literal {
-    Number(BigRational),
+    Number(Number),
     CharString(String),
     Boolean(bool),
     Map(Vec<(Expression, Expression)>),
+    List(Vec<Expression>),
     Fn(Box<Vec<String>>, Box<Vec<Statement>>),
 }
-// TODO: Vec(Vec<Value>)
 
 #[derive(Clone, Eq, Debug, Hash, Ord, PartialEq, PartialOrd)]
 pub enum Statement {
-    Assign(bool, String, Box<Expression>),
-    IndexAssign(Box<Expression>, Box<Expression>, Box<Expression>),
-    Call(Box<Expression>, Vec<Expression>),
-    Raise(Expression),
-    Rescue(Pattern, Box<Vec<Statement>>),
+    Assign(bool, String, Box<Expression>, Span),
+    IndexAssign(Box<Expression>, Box<Expression>, Box<Expression>, Span),
+    Call(Box<Expression>, Vec<Expression>, Span),
+    Raise(Expression, Span),
+    Reraise(Option<Expression>, Span),
+    Propagate(Option<Expression>, Span),
+    Rescue(Pattern, Option<Box<Expression>>, Box<Vec<Statement>>, Span),
+}
+
+impl Statement {
+    pub fn span(&self) -> Span {
+        match self {
+            &Statement::Assign(_, _, _, span)
+            | &Statement::IndexAssign(_, _, _, span)
+            | &Statement::Call(_, _, span)
+            | &Statement::Raise(_, span)
+            | &Statement::Reraise(_, span)
+            | &Statement::Propagate(_, span)
+            | &Statement::Rescue(_, _, _, span) => span,
+        }
+    }
 }
 
 #[derive(Clone, Eq, Debug, Hash, Ord, PartialEq, PartialOrd)]
 pub enum Expression {
-    BinOp(String, Box<Expression>, Box<Expression>),
-    Literal(Literal),
-    Identifier(String),
-    IndexAccess(Box<Expression>, Box<Expression>),
-    Import(Box<Expression>),
+    BinOp(String, Box<Expression>, Box<Expression>, Span),
+    UnaryOp(String, Box<Expression>, Span),
+    Literal(Literal, Span),
+    Identifier(String, Span),
+    IndexAccess(Box<Expression>, Box<Expression>, Span),
+    Import(Box<Expression>, Span),
+}
+
+impl Expression {
+    pub fn span(&self) -> Span {
+        match self {
+            &Expression::BinOp(_, _, _, span)
+            | &Expression::UnaryOp(_, _, span)
+            | &Expression::Literal(_, span)
+            | &Expression::Identifier(_, span)
+            | &Expression::IndexAccess(_, _, span)
+            | &Expression::Import(_, span) => span,
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -36,6 +116,39 @@ pub struct StringMatcher {
     pub regex: Regex,
 }
 
+impl StringMatcher {
+    // Turns the named groups of a regex into plain ones. String patterns bind
+    // groups by position, and joining regexes that name a group the same way
+    // would make an invalid regex.
+    pub fn strip_group_names(regex: &str) -> String {
+        let mut stripped = String::new();
+        let mut chars = regex.char_indices().peekable();
+        let mut class_depth = 0;
+        while let Some((index, c)) = chars.next() {
+            stripped.push(c);
+            match c {
+                '\\' => stripped.extend(chars.next().map(|(_, c)| c)),
+                '[' => {
+                    class_depth += 1;
+                    // A bracket right at the start of a class is part of it.
+                    for leading in ['^', ']'] {
+                        if chars.peek().map(|&(_, c)| c) == Some(leading) {
+                            stripped.push(leading);
+                            chars.next();
+                        }
+                    }
+                }
+                ']' if class_depth > 0 => class_depth -= 1,
+                '(' if class_depth == 0 && regex[index + 1..].starts_with("?P<") => {
+                    chars.find(|&(_, c)| c == '>');
+                }
+                _ => {}
+            }
+        }
+        stripped
+    }
+}
+
 impl PartialEq for StringMatcher {
     fn eq(&self, other: &StringMatcher) -> bool {
         self.regex.as_str().eq(other.regex.as_str())
@@ -62,14 +175,98 @@ impl Hash for StringMatcher {
     }
 }
 
+// The kinds of values type patterns can test for.
+#[derive(Clone, Copy, Eq, Debug, Hash, Ord, PartialEq, PartialOrd)]
+pub enum ValueType {
+    Number,
+    CharString,
+    Boolean,
+    Map,
+    List,
+    Function,
+}
+
 #[derive(Clone, Eq, Debug, Hash, Ord, PartialEq, PartialOrd)]
 pub enum Pattern {
-    Number(BigRational),
-    CharString(String),
-    Boolean(bool),
-    Map(Vec<(Pattern, Pattern)>),
-    Identifier(String),
-    StringMatch(Vec<String>, StringMatcher),
+    Number(Number, Span),
+    CharString(String, Span),
+    Boolean(bool, Span),
+    // The flag is set for exact maps, which can't have other keys.
+    Map(Vec<(Pattern, Pattern)>, bool, Span),
+    List(Vec<Pattern>, Option<String>, Span),
+    Identifier(String, Span),
+    // What each group of the regex binds, if anything, and as what type.
+    StringMatch(Vec<Option<(String, ValueType)>>, StringMatcher, Span),
+    Wildcard(Span),
+    Alternatives(Vec<Pattern>, Span),
+    As(Box<Pattern>, String, Span),
+    // Values equal to the current value of a binding. The parser leaves the
+    // binding unresolved, the compiler resolves it in the rescue's scope.
+    Pin(Rc<Binding>, Span),
+    Type(ValueType, Box<Pattern>, Span),
+    // Numbers between both bounds, included.
+    Range(Number, Number, Span),
+}
+
+impl Pattern {
+    // The names the pattern binds when it matches.
+    pub fn bound_names(&self) -> BTreeSet<String> {
+        let mut names = BTreeSet::new();
+        self.collect_names(&mut names);
+        names
+    }
+
+    pub fn has_pins(&self) -> bool {
+        match self {
+            Pattern::Pin(..) => true,
+            Pattern::Map(pairs, _, _) => pairs.iter().any(|(_, value)| value.has_pins()),
+            Pattern::List(items, _, _) | Pattern::Alternatives(items, _) => {
+                items.iter().any(|item| item.has_pins())
+            }
+            Pattern::As(pattern, _, _) | Pattern::Type(_, pattern, _) => {
+                pattern.has_pins()
+            }
+            _ => false,
+        }
+    }
+
+    fn collect_names(&self, names: &mut BTreeSet<String>) {
+        match self {
+            Pattern::Number(..)
+            | Pattern::CharString(..)
+            | Pattern::Boolean(..)
+            | Pattern::Wildcard(..)
+            | Pattern::Pin(..)
+            | Pattern::Range(..) => {}
+            Pattern::Map(pairs, _, _) => {
+                for (_, value) in pairs.iter() {
+                    value.collect_names(names);
+                }
+            }
+            Pattern::List(items, tail, _) => {
+                for item in items.iter() {
+                    item.collect_names(names);
+                }
+                names.extend(tail.iter().cloned());
+            }
+            Pattern::Identifier(name, _) => {
+                names.insert(name.to_owned());
+            }
+            Pattern::StringMatch(bindings, _, _) => {
+                names.extend(bindings.iter().flat_map(|binding| binding.iter().map(|b| b.0.clone())))
+            }
+            Pattern::Alternatives(alternatives, _) => {
+                for alternative in alternatives.iter() {
+                    alternative.collect_names(names);
+                }
+            }
+            Pattern::As(pattern, name, _) => {
+                pattern.collect_names(names);
+                names.insert(name.to_owned());
+            }
+            Pattern::Type(_, pattern, _) => pattern.collect_names(names),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -83,6 +280,19 @@ mod test {
         }
     }
 
+    #[test]
+    fn spans_are_ignored_in_comparisons() {
+        assert_eq!(Span::new(0, 1), Span::new(4, 8));
+        assert_eq!(
+            Expression::Identifier("a".to_owned(), Span::new(0, 1)),
+            Expression::Identifier("a".to_owned(), Span::new(4, 5))
+        );
+        assert_ne!(
+            Expression::Identifier("a".to_owned(), Span::new(0, 1)),
+            Expression::Identifier("b".to_owned(), Span::new(0, 1))
+        );
+    }
+
     #[test]
     fn string_matcher_eq() {
         assert_eq!(string_matcher("abcd"), string_matcher("abcd"));
@@ -96,4 +306,20 @@ mod test {
             string_matcher("abcd").hash(&mut hasher)
         );
     }
+
+    #[test]
+    fn strips_group_names() {
+        assert_eq!(
+            "([a-z]+)-(x)",
+            StringMatcher::strip_group_names("(?P<a>[a-z]+)-(?P<b>x)")
+        );
+        assert_eq!(
+            r"\(?P<a>[(?P<b>])",
+            StringMatcher::strip_group_names(r"\(?P<a>[(?P<b>])")
+        );
+        assert_eq!(
+            r"\\([[:alpha:]](?i:x))",
+            StringMatcher::strip_group_names(r"\\(?P<a>[[:alpha:]](?i:x))")
+        );
+    }
 }
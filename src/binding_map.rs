@@ -1,110 +1,213 @@
 use std::cell::RefCell;
-use std::collections::BTreeMap;
 use std::rc::Rc;
 use value::Value;
 
+// The names of a scope's slots, in order. Compiled code only uses the slots,
+// names are kept for natives and for looking at bindings from outside.
+pub type Layout = Rc<Vec<String>>;
+
+// A name as the compiler resolved it: the depth and slot of every enclosing
+// scope that can bind it, innermost first. Slots are only set once their `let`
+// has run, so the name refers to the first one that's set.
+#[derive(Clone, Eq, Debug, Hash, PartialEq, PartialOrd, Ord)]
+pub struct Binding {
+    pub name: String,
+    pub slots: Vec<(usize, usize)>,
+}
+
+impl Binding {
+    pub fn new(name: &str, slots: Vec<(usize, usize)>) -> Binding {
+        Binding {
+            name: name.to_owned(),
+            slots,
+        }
+    }
+}
+
+#[derive(Eq, Debug, PartialEq, PartialOrd, Ord)]
+struct Scope {
+    names: RefCell<Layout>,
+    values: RefCell<Vec<Option<Value>>>,
+    parent: Option<BindingMap>,
+}
+
 #[derive(Clone, Eq, Debug, PartialEq, PartialOrd, Ord)]
 pub struct BindingMap {
-    map: Rc<RefCell<BTreeMap<String, Value>>>,
-    parent: Option<Box<BindingMap>>,
+    scope: Rc<Scope>,
 }
 
 impl BindingMap {
-    pub fn new(parent: Option<&BindingMap>) -> BindingMap {
-        let parent = match parent {
-            Some(map) => Some(Box::new(map.clone())),
-            None => None,
-        };
+    pub fn new(names: &Layout, parent: Option<&BindingMap>) -> BindingMap {
         BindingMap {
-            map: Rc::new(RefCell::new(BTreeMap::new())),
-            parent: parent,
+            scope: Rc::new(Scope {
+                names: RefCell::new(names.clone()),
+                values: RefCell::new(vec![None; names.len()]),
+                parent: parent.cloned(),
+            }),
         }
     }
 
-    pub fn fetch(&self, binding_name: &String) -> Option<Value> {
-        match self.map.borrow().get(binding_name) {
-            Some(value) => Some(value.clone()),
-            None => match self.parent {
-                Some(ref parent) => parent.fetch(binding_name),
-                None => None,
-            },
+    fn ancestor(&self, depth: usize) -> &BindingMap {
+        let mut map = self;
+        for _ in 0..depth {
+            map = map
+                .scope
+                .parent
+                .as_ref()
+                .expect("binding resolved past the outermost scope");
         }
+        map
+    }
+
+    // The scope and slot a binding refers to, if any of its slots are set.
+    fn resolve(&self, binding: &Binding) -> Option<(&BindingMap, usize)> {
+        binding
+            .slots
+            .iter()
+            .map(|&(depth, slot)| (self.ancestor(depth), slot))
+            .find(|&(map, slot)| map.scope.values.borrow()[slot].is_some())
     }
 
-    pub fn local_assign(&mut self, binding_name: &String, value: Value) {
-        self.map.borrow_mut().insert(binding_name.to_owned(), value);
+    pub fn get(&self, binding: &Binding) -> Option<Value> {
+        self.resolve(binding)
+            .and_then(|(map, slot)| map.scope.values.borrow()[slot].clone())
+    }
+
+    pub fn set(&self, binding: &Binding, value: Value) -> Result<(), String> {
+        match self.resolve(binding) {
+            Some((map, slot)) => {
+                map.set_local(slot, value);
+                Ok(())
+            }
+            None => Err(format!("No such binding {}", binding.name)),
+        }
     }
 
-    pub fn assign(&mut self, binding_name: &String, value: Value) {
-        match self.has_binding(binding_name) {
-            true => self.local_assign(binding_name, value),
-            false => match self.parent {
-                Some(ref mut parent) => parent.assign(binding_name, value),
-                None => panic!("no such binding"),
+    pub fn set_local(&self, slot: usize, value: Value) {
+        self.scope.values.borrow_mut()[slot] = Some(value);
+    }
+
+    fn slot(&self, binding_name: &String) -> Option<usize> {
+        self.scope
+            .names
+            .borrow()
+            .iter()
+            .rposition(|name| name == binding_name)
+    }
+
+    // Looks a binding up by name, as resolved bindings would find it.
+    pub fn fetch(&self, binding_name: &String) -> Option<Value> {
+        let value = self
+            .slot(binding_name)
+            .and_then(|slot| self.scope.values.borrow()[slot].clone());
+        match value {
+            Some(value) => Some(value),
+            None => match self.scope.parent {
+                Some(ref parent) => parent.fetch(binding_name),
+                None => None,
             },
         }
     }
 
-    pub fn has_binding(&self, binding_name: &String) -> bool {
-        self.map.borrow().contains_key(binding_name)
+    // Binds a name in this scope, adding a slot for it if the compiler didn't.
+    pub fn local_assign(&self, binding_name: &String, value: Value) {
+        match self.slot(binding_name) {
+            Some(slot) => self.set_local(slot, value),
+            None => {
+                Rc::make_mut(&mut *self.scope.names.borrow_mut()).push(binding_name.to_owned());
+                self.scope.values.borrow_mut().push(Some(value));
+            }
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use std::cell::RefCell;
-    use std::collections::BTreeMap;
-    use std::rc::Rc;
     use test_helpers::*;
 
-    #[test]
-    fn new() {
-        assert_eq!(
-            Rc::new(RefCell::new(BTreeMap::new())),
-            BindingMap::new(None).map
-        )
+    fn layout(names: Vec<&str>) -> Layout {
+        Rc::new(names.into_iter().map(|name| name.to_owned()).collect())
     }
 
     #[test]
-    fn has_binding() {
-        let map = BindingMap::new(None);
-        assert_eq!(false, map.has_binding(&"toto".to_owned()))
+    fn new() {
+        let map = BindingMap::new(&layout(vec!["a", "b"]), None);
+        assert_eq!(vec![None, None], *map.scope.values.borrow());
+        assert_eq!(None, map.fetch(&"a".to_owned()))
     }
 
     #[test]
     fn local_assign() {
-        let mut map = BindingMap::new(None);
-        map.local_assign(&"toto".to_owned(), v_string("value"));
-        assert!(map.has_binding(&"toto".to_owned()))
+        let map = BindingMap::new(&layout(vec!["titi"]), None);
+        map.local_assign(&"titi".to_owned(), v_string("titi"));
+        map.local_assign(&"toto".to_owned(), v_string("toto"));
+        assert_eq!(
+            vec![Some(v_string("titi")), Some(v_string("toto"))],
+            *map.scope.values.borrow()
+        );
+        assert_eq!(Some(v_string("toto")), map.fetch(&"toto".to_owned()))
     }
 
     #[test]
     fn fetch() {
-        let mut map = BindingMap::new(None);
-        map.local_assign(&"toto".to_owned(), v_string("value"));
-        assert_eq!(Some(v_string("value")), map.fetch(&"toto".to_owned()))
+        let map = BindingMap::new(&layout(vec!["toto"]), None);
+        map.set_local(0, v_string("value"));
+        assert_eq!(Some(v_string("value")), map.fetch(&"toto".to_owned()));
+        assert_eq!(
+            Some(v_string("value")),
+            map.get(&Binding::new("toto", vec![(0, 0)]))
+        )
     }
 
     #[test]
     fn delegates_fetch() {
-        let mut parent = BindingMap::new(None);
-        parent.local_assign(&"toto".to_owned(), v_string("value"));
-        let map = BindingMap::new(Some(&parent));
+        let parent = BindingMap::new(&layout(vec!["toto"]), None);
+        parent.set_local(0, v_string("value"));
+        let map = BindingMap::new(&layout(vec!["titi"]), Some(&parent));
 
-        assert_eq!(Some(v_string("value")), map.fetch(&"toto".to_owned()))
+        assert_eq!(Some(v_string("value")), map.fetch(&"toto".to_owned()));
+        assert_eq!(
+            Some(v_string("value")),
+            map.get(&Binding::new("toto", vec![(1, 0)]))
+        )
+    }
+
+    #[test]
+    fn gets_the_first_slot_that_is_set() {
+        let parent = BindingMap::new(&layout(vec!["toto"]), None);
+        parent.set_local(0, v_string("parent"));
+        let map = BindingMap::new(&layout(vec!["toto"]), Some(&parent));
+        let binding = Binding::new("toto", vec![(0, 0), (1, 0)]);
+
+        assert_eq!(Some(v_string("parent")), map.get(&binding));
+        map.set_local(0, v_string("local"));
+        assert_eq!(Some(v_string("local")), map.get(&binding));
+        assert_eq!(Some(v_string("local")), map.fetch(&"toto".to_owned()));
     }
 
     #[test]
     fn delegates_assign() {
-        let mut parent = BindingMap::new(None);
-        parent.local_assign(&"toto".to_owned(), v_string("value"));
-        let mut map = BindingMap::new(Some(&parent));
-        map.assign(&"toto".to_owned(), v_string("new_value"));
+        let parent = BindingMap::new(&layout(vec!["toto"]), None);
+        parent.set_local(0, v_string("value"));
+        let map = BindingMap::new(&layout(vec!["toto"]), Some(&parent));
+        let binding = Binding::new("toto", vec![(0, 0), (1, 0)]);
+        assert_eq!(Ok(()), map.set(&binding, v_string("new_value")));
 
         assert_eq!(
             Some(v_string("new_value")),
             parent.fetch(&"toto".to_owned())
-        )
+        );
+        assert_eq!(None, map.get(&Binding::new("toto", vec![(0, 0)])));
+    }
+
+    #[test]
+    fn assign_fails_without_a_binding() {
+        let parent = BindingMap::new(&layout(vec!["toto"]), None);
+        let map = BindingMap::new(&layout(vec![]), Some(&parent));
+
+        assert_err!(map.set(&Binding::new("toto", vec![(1, 0)]), v_string("value")));
+        assert_eq!(None, parent.fetch(&"toto".to_owned()));
+        assert_eq!(None, map.fetch(&"toto".to_owned()));
     }
 }
@@ -1,37 +1,53 @@
-use binding_map::BindingMap;
+use ast::Span;
+use binding_map::{BindingMap, Layout};
 use instructions::InstructionSequence;
+use source_map::SourceMap;
 use std::rc::Rc;
 use value::Value;
 
 #[derive(Clone, Eq, Debug, PartialEq, PartialOrd, Ord)]
 pub struct Closure {
     pub instructions: Rc<InstructionSequence>,
+    pub source_map: Rc<SourceMap>,
+    // The slots of the scope the closure runs in, its arguments first.
+    pub names: Layout,
     pub parent_bindings: BindingMap,
+    // Where the closure was defined, when it comes from source.
+    pub span: Option<Span>,
 }
 
 impl Closure {
-    pub fn new(instructions: Rc<InstructionSequence>, parent_bindings: &BindingMap) -> Closure {
+    pub fn new(
+        instructions: Rc<InstructionSequence>,
+        source_map: Rc<SourceMap>,
+        names: Layout,
+        parent_bindings: &BindingMap,
+    ) -> Closure {
         Closure {
             instructions: instructions,
+            source_map,
+            names,
             parent_bindings: parent_bindings.clone(),
+            span: None,
         }
     }
 
     pub fn blank() -> Closure {
         Closure {
             instructions: Rc::new(vec![]),
-            parent_bindings: BindingMap::new(None),
+            source_map: Rc::new(SourceMap::new()),
+            names: Rc::new(vec![]),
+            parent_bindings: BindingMap::new(&Rc::new(vec![]), None),
+            span: None,
         }
     }
 
-    pub fn init_map(&self, local_bindings: Vec<(String, Value)>) -> BindingMap {
-        let map = BindingMap::new(Some(&self.parent_bindings));
-        local_bindings
-            .into_iter()
-            .fold(map, |mut map, (arg_name, value)| {
-                map.local_assign(&arg_name, value);
-                map
-            })
+    pub fn init_map(&self, args: Vec<Value>) -> BindingMap {
+        let map = BindingMap::new(&self.names, Some(&self.parent_bindings));
+        for (slot, value) in args.into_iter().enumerate() {
+            map.set_local(slot, value);
+        }
+        map
     }
 }
 
@@ -42,10 +58,15 @@ mod test {
 
     #[test]
     fn enclose_assigns_new_bidings() {
-        let parent_map = BindingMap::new(None);
-        let closure = Closure::new(Rc::new(vec![]), &parent_map);
+        let parent_map = BindingMap::new(&Rc::new(vec![]), None);
+        let closure = Closure::new(
+            Rc::new(vec![]),
+            Rc::new(SourceMap::new()),
+            Rc::new(vec!["toto".to_owned()]),
+            &parent_map,
+        );
 
-        let new_map = closure.init_map(vec![("toto".to_owned(), v_number(1, 1))]);
+        let new_map = closure.init_map(vec![v_number(1, 1)]);
         assert_eq!(new_map.fetch(&"toto".to_owned()), Some(v_number(1, 1)));
     }
 }
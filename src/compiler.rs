@@ -1,90 +1,235 @@
 use ast::*;
+use binding_map::{Binding, Layout};
+use exception_handler::Guard;
 use instructions::*;
+use source_map::SourceMap;
 use std::rc::Rc;
 
-fn compile_statement(statement: &Statement) -> InstructionSequence {
-    match statement {
-        &Statement::Assign(local, ref binding_name, ref expression) => {
-            let mut instructions = compile_expression(expression);
-            let instruction = match local {
-                true => Instruction::LocalAssign(binding_name.to_owned()),
-                false => Instruction::Assign(binding_name.to_owned()),
-            };
-            instructions.push(instruction);
-            instructions
-        }
-        &Statement::Call(ref target, ref expressions) => {
-            let mut instructions = expressions
-                .iter()
-                .flat_map(|exp| compile_expression(exp))
-                .collect::<InstructionSequence>();
-            instructions.extend(compile_expression(target).iter().cloned());
-            instructions.push(Instruction::Call(expressions.len()));
-            instructions
-        }
-        &Statement::Rescue(ref pattern, ref statements) => {
-            let instructions = statements
-                .iter()
-                .flat_map(|stmt| compile_statement(stmt))
-                .collect::<InstructionSequence>();
+// Collects the instructions of a sequence along with the span of the node
+// each of them was compiled from.
+struct Emitter {
+    instructions: InstructionSequence,
+    source_map: SourceMap,
+    // The slots of the scopes the sequence runs in, the innermost one last.
+    scopes: Vec<Layout>,
+}
 
-            vec![
-                Instruction::Rescue(Rc::new(pattern.clone()), Rc::new(instructions)),
-            ]
+impl Emitter {
+    fn new(scopes: Vec<Layout>) -> Emitter {
+        Emitter {
+            instructions: InstructionSequence::new(),
+            source_map: SourceMap::new(),
+            scopes,
         }
-        &Statement::Raise(ref expression) => {
-            let mut instructions = compile_expression(expression);
-            instructions.push(Instruction::Raise);
-            instructions
+    }
+
+    // An emitter for statements running in a new scope, where `names` are
+    // bound on entry.
+    fn nested(&self, names: Vec<String>, statements: &[Statement]) -> Emitter {
+        let mut scopes = self.scopes.clone();
+        scopes.push(Rc::new(scope_names(names, statements)));
+        Emitter::new(scopes)
+    }
+
+    fn resolve(&self, name: &str) -> Rc<Binding> {
+        let slots = self
+            .scopes
+            .iter()
+            .rev()
+            .enumerate()
+            .filter_map(|(depth, names)| {
+                names
+                    .iter()
+                    .rposition(|bound| bound == name)
+                    .map(|slot| (depth, slot))
+            })
+            .collect();
+        Rc::new(Binding::new(name, slots))
+    }
+
+    // The pattern with its pins resolved in the scope the rescue block is
+    // registered in, which is where they're looked up when matching.
+    fn resolve_pins(&self, pattern: &Pattern) -> Pattern {
+        match pattern {
+            &Pattern::Pin(ref binding, span) => Pattern::Pin(self.resolve(&binding.name), span),
+            &Pattern::Map(ref pairs, exact, span) => Pattern::Map(
+                pairs
+                    .iter()
+                    .map(|(key, value)| (self.resolve_pins(key), self.resolve_pins(value)))
+                    .collect(),
+                exact,
+                span,
+            ),
+            &Pattern::List(ref items, ref tail, span) => Pattern::List(
+                items.iter().map(|item| self.resolve_pins(item)).collect(),
+                tail.clone(),
+                span,
+            ),
+            &Pattern::Alternatives(ref alternatives, span) => Pattern::Alternatives(
+                alternatives
+                    .iter()
+                    .map(|alternative| self.resolve_pins(alternative))
+                    .collect(),
+                span,
+            ),
+            &Pattern::As(ref pattern, ref name, span) => {
+                Pattern::As(Box::new(self.resolve_pins(pattern)), name.to_owned(), span)
+            }
+            &Pattern::Type(value_type, ref pattern, span) => {
+                Pattern::Type(value_type, Box::new(self.resolve_pins(pattern)), span)
+            }
+            pattern => pattern.clone(),
         }
-        &Statement::IndexAssign(ref target, ref property, ref value) => {
-            let mut instructions = compile_expression(target);
-            instructions.extend(compile_expression(property).iter().cloned());
-            instructions.extend(compile_expression(value).iter().cloned());
-            instructions.push(Instruction::IndexAssign);
-            instructions
+    }
+
+    fn local_slot(&self, name: &str) -> usize {
+        self.scopes
+            .last()
+            .and_then(|names| names.iter().rposition(|bound| bound == name))
+            .expect("let outside of a scope")
+    }
+
+    fn statements(
+        mut self,
+        statements: &[Statement],
+    ) -> (Layout, InstructionSequence, SourceMap) {
+        for statement in statements.iter() {
+            self.emit(Instruction::Clear, statement.span());
+            self.statement(statement);
         }
-        //        s => panic!("not implemented: {:?}", s),
+
+        let names = self.scopes.last().cloned().unwrap_or_default();
+        (names, self.instructions, self.source_map)
     }
-}
 
-fn compile_expression(expression: &Expression) -> InstructionSequence {
-    match expression {
-        &Expression::Literal(ref literal) => match literal {
-            &Literal::Map(ref pairs) => {
-                let mut map_instructions = pairs
-                    .iter()
-                    .flat_map(|&(ref key, ref value)| {
-                        let mut insns = compile_expression(key);
-                        insns.extend(compile_expression(value).iter().cloned());
-                        insns
+    fn emit(&mut self, instruction: Instruction, span: Span) {
+        self.instructions.push(instruction);
+        self.source_map.push(span);
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match *statement {
+            Statement::Assign(local, ref binding_name, ref expression, span) => {
+                self.expression(expression);
+                let instruction = match local {
+                    true => Instruction::LocalAssign(self.local_slot(binding_name)),
+                    false => Instruction::Assign(self.resolve(binding_name)),
+                };
+                self.emit(instruction, span);
+            }
+            Statement::Call(ref target, ref expressions, span) => {
+                for expression in expressions.iter() {
+                    self.expression(expression);
+                }
+                self.expression(target);
+                self.emit(Instruction::Call(expressions.len()), span);
+            }
+            Statement::Rescue(ref pattern, ref guard, ref statements, span) => {
+                let body = self.nested(pattern.bound_names().into_iter().collect(), statements);
+                // Guards run in the scope of the rescue block, before its body.
+                let guard = guard.as_ref().map(|guard| {
+                    let mut emitter = Emitter::new(body.scopes.clone());
+                    emitter.expression(guard);
+                    Rc::new(Guard {
+                        instructions: Rc::new(emitter.instructions),
+                        source_map: Rc::new(emitter.source_map),
                     })
-                    .collect::<InstructionSequence>();
+                });
+                let (names, instructions, source_map) = body.statements(statements);
 
-                map_instructions.push(Instruction::MakeMap(pairs.len()));
-                map_instructions
+                let pattern = self.resolve_pins(pattern);
+                self.emit(
+                    Instruction::Rescue(
+                        Rc::new(pattern),
+                        guard,
+                        names,
+                        Rc::new(instructions),
+                        Rc::new(source_map),
+                    ),
+                    span,
+                );
+            }
+            Statement::Raise(ref expression, span) => {
+                self.expression(expression);
+                self.emit(Instruction::Raise, span);
+            }
+            Statement::Reraise(ref expression, span) => {
+                self.reraised_value(expression, span);
+                self.emit(Instruction::Reraise, span);
+            }
+            Statement::Propagate(ref expression, span) => {
+                self.reraised_value(expression, span);
+                self.emit(Instruction::Propagate, span);
+            }
+            Statement::IndexAssign(ref target, ref property, ref value, span) => {
+                self.expression(target);
+                self.expression(property);
+                self.expression(value);
+                self.emit(Instruction::IndexAssign, span);
             }
-            _ => vec![Instruction::Push(literal.to_owned())],
-        },
-        &Expression::Identifier(ref binding_name) => {
-            vec![Instruction::Fetch(binding_name.to_owned())]
-        }
-        &Expression::BinOp(ref op, ref left, ref right) => {
-            let mut instructions = compile_expression(&*left);
-            instructions.extend(compile_expression(&*right).iter().cloned());
-            instructions.push(compile_binop(&*op));
-            instructions
         }
-        &Expression::IndexAccess(ref target, ref property) => {
-            let mut instructions = compile_expression(&*target);
-            instructions.extend(compile_expression(&*property).iter().cloned());
-            instructions.push(Instruction::IndexAccess);
-            instructions
+    }
+
+    fn reraised_value(&mut self, expression: &Option<Expression>, span: Span) {
+        match expression {
+            Some(expression) => self.expression(expression),
+            &None => self.emit(Instruction::Rescued, span),
         }
-        &Expression::Import(ref name) => {
-            let mut instructions = compile_expression(name);
-            instructions.push(Instruction::Import);
-            instructions
+    }
+
+    fn expression(&mut self, expression: &Expression) {
+        match *expression {
+            Expression::Literal(ref literal, span) => match literal {
+                Literal::Map(pairs) => {
+                    for (key, value) in pairs.iter() {
+                        self.expression(key);
+                        self.expression(value);
+                    }
+                    self.emit(Instruction::MakeMap(pairs.len()), span);
+                }
+                Literal::List(items) => {
+                    for item in items.iter() {
+                        self.expression(item);
+                    }
+                    self.emit(Instruction::MakeList(items.len()), span);
+                }
+                Literal::Fn(args, statements) => {
+                    let body = self.nested((**args).clone(), statements);
+                    let (names, instructions, source_map) = body.statements(statements);
+                    self.emit(
+                        Instruction::MakeClosure(
+                            Rc::new(args.clone()),
+                            names,
+                            Rc::new(instructions),
+                            Rc::new(source_map),
+                        ),
+                        span,
+                    );
+                }
+                _ => self.emit(Instruction::Push(literal.to_owned()), span),
+            },
+            Expression::Identifier(ref binding_name, span) => {
+                let binding = self.resolve(binding_name);
+                self.emit(Instruction::Fetch(binding), span)
+            }
+            Expression::BinOp(ref op, ref left, ref right, span) => {
+                self.expression(left);
+                self.expression(right);
+                self.emit(compile_binop(op), span);
+            }
+            Expression::UnaryOp(ref op, ref operand, span) => {
+                self.expression(operand);
+                self.emit(compile_unaryop(op), span);
+            }
+            Expression::IndexAccess(ref target, ref property, span) => {
+                self.expression(target);
+                self.expression(property);
+                self.emit(Instruction::IndexAccess, span);
+            }
+            Expression::Import(ref name, span) => {
+                self.expression(name);
+                self.emit(Instruction::Import, span);
+            }
         }
     }
 }
@@ -95,25 +240,50 @@ fn compile_binop(op: &str) -> Instruction {
         "-" => Op::Sub,
         "/" => Op::Div,
         "*" => Op::Mul,
+        "//" => Op::FloorDiv,
+        "%" => Op::Mod,
+        "**" => Op::Pow,
         "==" => Op::Eq,
+        "!=" => Op::NotEq,
         ">=" => Op::GtEq,
         ">" => Op::Gt,
         "<=" => Op::LtEq,
         "<" => Op::Lt,
+        "and" => Op::And,
+        "or" => Op::Or,
         _ => panic!("Unsupported binary operation: {:?}", op),
     };
     Instruction::BinOp(op_value)
 }
 
-pub fn compile(statements: &Vec<Statement>) -> InstructionSequence {
-    let mut instructions = InstructionSequence::new();
+fn compile_unaryop(op: &str) -> Instruction {
+    let op_value = match op {
+        "-" => UnaryOp::Neg,
+        "not" => UnaryOp::Not,
+        _ => panic!("Unsupported unary operation: {:?}", op),
+    };
+    Instruction::UnaryOp(op_value)
+}
 
+// The slots of a scope: the names bound on entry, then those the statements
+// assign with `let`. Functions and rescue blocks defined in the statements
+// get scopes of their own.
+fn scope_names(mut names: Vec<String>, statements: &[Statement]) -> Vec<String> {
     for statement in statements.iter() {
-        instructions.push(Instruction::Clear);
-        instructions.extend(compile_statement(&statement).iter().cloned());
+        if let &Statement::Assign(true, ref name, _, _) = statement {
+            if !names.contains(name) {
+                names.push(name.to_owned());
+            }
+        }
     }
+    names
+}
 
-    instructions
+// Compiles a program, along with the slots of its outermost scope.
+pub fn compile(statements: &[Statement]) -> (Layout, InstructionSequence, SourceMap) {
+    Emitter::new(vec![])
+        .nested(vec![], statements)
+        .statements(statements)
 }
 
 #[cfg(test)]
@@ -121,12 +291,31 @@ mod test {
     use super::*;
     use test_helpers::*;
 
+    fn compile_statement(statement: &Statement) -> InstructionSequence {
+        let mut emitter = Emitter::new(vec![]);
+        emitter.statement(statement);
+        emitter.instructions
+    }
+
+    fn compile_expression(expression: &Expression) -> InstructionSequence {
+        let mut emitter = Emitter::new(vec![]);
+        emitter.expression(expression);
+        emitter.instructions
+    }
+
+    fn fetch(name: &str, slots: Vec<(usize, usize)>) -> Instruction {
+        Instruction::Fetch(Rc::new(Binding::new(name, slots)))
+    }
+
     #[test]
     fn compiles_arithmetics() {
         assert_eq!(compile_binop("+"), Instruction::BinOp(Op::Add));
         assert_eq!(compile_binop("-"), Instruction::BinOp(Op::Sub));
         assert_eq!(compile_binop("/"), Instruction::BinOp(Op::Div));
         assert_eq!(compile_binop("*"), Instruction::BinOp(Op::Mul));
+        assert_eq!(compile_binop("//"), Instruction::BinOp(Op::FloorDiv));
+        assert_eq!(compile_binop("%"), Instruction::BinOp(Op::Mod));
+        assert_eq!(compile_binop("**"), Instruction::BinOp(Op::Pow));
     }
 
     #[test]
@@ -136,16 +325,70 @@ mod test {
         assert_eq!(compile_binop(">"), Instruction::BinOp(Op::Gt));
         assert_eq!(compile_binop("<="), Instruction::BinOp(Op::LtEq));
         assert_eq!(compile_binop("<"), Instruction::BinOp(Op::Lt));
+        assert_eq!(compile_binop("!="), Instruction::BinOp(Op::NotEq));
+    }
+
+    #[test]
+    fn compiles_boolean_logic() {
+        assert_eq!(compile_binop("and"), Instruction::BinOp(Op::And));
+        assert_eq!(compile_binop("or"), Instruction::BinOp(Op::Or));
+        assert_eq!(compile_unaryop("not"), Instruction::UnaryOp(UnaryOp::Not));
+    }
+
+    #[test]
+    fn compiles_unary_operations() {
+        assert_eq!(compile_unaryop("-"), Instruction::UnaryOp(UnaryOp::Neg));
     }
 
     #[test]
     fn compiles_literal_expressions() {
         assert_eq!(
             compile_expression(&e_literal(l_number(1, 1))),
-            vec![Instruction::Push(Literal::Number(build_ratio(1, 1)))]
+            vec![Instruction::Push(l_number(1, 1))]
         )
     }
 
+    #[test]
+    fn compiles_list_literals() {
+        assert_eq!(
+            compile_expression(&e_literal(l_list(vec![
+                e_literal(l_number(1, 1)),
+                e_identifier("a"),
+            ]))),
+            vec![
+                Instruction::Push(l_number(1, 1)),
+                fetch("a", vec![]),
+                Instruction::MakeList(2),
+            ]
+        )
+    }
+
+    #[test]
+    fn compiles_function_bodies_ahead_of_time() {
+        let instructions = compile_expression(&e_literal(l_function(
+            vec!["a".to_owned()],
+            vec![s_raise(e_identifier("a"))],
+        )));
+
+        assert_eq!(1, instructions.len());
+        match instructions[0] {
+            Instruction::MakeClosure(ref args, ref names, ref body, ref source_map) => {
+                assert_eq!(vec!["a".to_owned()], ***args);
+                assert_eq!(vec!["a".to_owned()], **names);
+                assert_eq!(
+                    vec![
+                        Instruction::Clear,
+                        fetch("a", vec![(0, 0)]),
+                        Instruction::Raise
+                    ],
+                    **body
+                );
+                assert!(source_map.span_at(0).is_some());
+            }
+            ref instruction => panic!("expected MakeClosure, got {:?}", instruction),
+        }
+    }
+
     #[test]
     fn compiles_binop_expressions() {
         assert_eq!(
@@ -155,18 +398,268 @@ mod test {
                 e_literal(l_number(2, 1)),
             )),
             vec![
-                Instruction::Push(Literal::Number(build_ratio(1, 1))),
-                Instruction::Push(Literal::Number(build_ratio(2, 1))),
+                Instruction::Push(l_number(1, 1)),
+                Instruction::Push(l_number(2, 1)),
                 Instruction::BinOp(Op::Add),
             ]
         )
     }
 
+    #[test]
+    fn compiles_grouped_expressions() {
+        assert_eq!(
+            compile_expression(&parse_expression("(1 + 2) * 3")),
+            vec![
+                Instruction::Push(l_number(1, 1)),
+                Instruction::Push(l_number(2, 1)),
+                Instruction::BinOp(Op::Add),
+                Instruction::Push(l_number(3, 1)),
+                Instruction::BinOp(Op::Mul),
+            ]
+        );
+        assert_eq!(
+            compile_expression(&parse_expression("1 + 2 * 3")),
+            vec![
+                Instruction::Push(l_number(1, 1)),
+                Instruction::Push(l_number(2, 1)),
+                Instruction::Push(l_number(3, 1)),
+                Instruction::BinOp(Op::Mul),
+                Instruction::BinOp(Op::Add),
+            ]
+        );
+    }
+
+    #[test]
+    fn compiles_arithmetics_left_to_right() {
+        assert_eq!(
+            compile_expression(&parse_expression("a - b - c")),
+            vec![
+                fetch("a", vec![]),
+                fetch("b", vec![]),
+                Instruction::BinOp(Op::Sub),
+                fetch("c", vec![]),
+                Instruction::BinOp(Op::Sub),
+            ]
+        );
+        assert_eq!(
+            compile_expression(&parse_expression("a / b * c")),
+            vec![
+                fetch("a", vec![]),
+                fetch("b", vec![]),
+                Instruction::BinOp(Op::Div),
+                fetch("c", vec![]),
+                Instruction::BinOp(Op::Mul),
+            ]
+        );
+    }
+
+    #[test]
+    fn compiles_operators_by_precedence() {
+        assert_eq!(
+            compile_expression(&parse_expression("a + b * c >= d - e / f")),
+            vec![
+                fetch("a", vec![]),
+                fetch("b", vec![]),
+                fetch("c", vec![]),
+                Instruction::BinOp(Op::Mul),
+                Instruction::BinOp(Op::Add),
+                fetch("d", vec![]),
+                fetch("e", vec![]),
+                fetch("f", vec![]),
+                Instruction::BinOp(Op::Div),
+                Instruction::BinOp(Op::Sub),
+                Instruction::BinOp(Op::GtEq),
+            ]
+        );
+    }
+
+    #[test]
+    fn compiles_exponentiation_right_to_left() {
+        assert_eq!(
+            compile_expression(&parse_expression("a * b ** c ** d % e")),
+            vec![
+                fetch("a", vec![]),
+                fetch("b", vec![]),
+                fetch("c", vec![]),
+                fetch("d", vec![]),
+                Instruction::BinOp(Op::Pow),
+                Instruction::BinOp(Op::Pow),
+                Instruction::BinOp(Op::Mul),
+                fetch("e", vec![]),
+                Instruction::BinOp(Op::Mod),
+            ]
+        );
+    }
+
+    #[test]
+    fn compiles_unary_expressions() {
+        assert_eq!(
+            compile_expression(&parse_expression("-a * b")),
+            vec![
+                fetch("a", vec![]),
+                Instruction::UnaryOp(UnaryOp::Neg),
+                fetch("b", vec![]),
+                Instruction::BinOp(Op::Mul),
+            ]
+        );
+        assert_eq!(
+            compile_expression(&parse_expression("-1")),
+            vec![Instruction::Push(l_number(-1, 1))]
+        );
+    }
+
+    #[test]
+    fn compiles_boolean_operators_by_precedence() {
+        assert_eq!(
+            compile_expression(&parse_expression("a or not b and c != d")),
+            vec![
+                fetch("a", vec![]),
+                fetch("b", vec![]),
+                Instruction::UnaryOp(UnaryOp::Not),
+                fetch("c", vec![]),
+                fetch("d", vec![]),
+                Instruction::BinOp(Op::NotEq),
+                Instruction::BinOp(Op::And),
+                Instruction::BinOp(Op::Or),
+            ]
+        );
+    }
+
+    #[test]
+    fn compiles_rescue_blocks_as_separate_statements() {
+        assert_eq!(
+            compile_statement(&parse_statements("rescue(x) do\nlet a = x\nend")[0]),
+            vec![Instruction::Rescue(
+                Rc::new(p_ident("x")),
+                None,
+                Rc::new(vec!["x".to_owned(), "a".to_owned()]),
+                Rc::new(vec![
+                    Instruction::Clear,
+                    fetch("x", vec![(0, 0)]),
+                    Instruction::LocalAssign(1),
+                ]),
+                Rc::new(SourceMap::new()),
+            )]
+        );
+    }
+
+    #[test]
+    fn resolves_identifiers_to_slots() {
+        let (names, instructions, _) = compile(&parse_statements(
+            "let a = 1\nlet f = fn(b) do\nlet a = b\nb = a\nc = d\nend",
+        ));
+        assert_eq!(vec!["a".to_owned(), "f".to_owned()], *names);
+        assert_eq!(Instruction::LocalAssign(0), instructions[2]);
+        assert_eq!(Instruction::LocalAssign(1), instructions[5]);
+
+        match instructions[4] {
+            Instruction::MakeClosure(_, ref names, ref body, _) => {
+                assert_eq!(vec!["b".to_owned(), "a".to_owned()], **names);
+                assert_eq!(
+                    vec![
+                        Instruction::Clear,
+                        fetch("b", vec![(0, 0)]),
+                        Instruction::LocalAssign(1),
+                        Instruction::Clear,
+                        fetch("a", vec![(0, 1), (1, 0)]),
+                        Instruction::Assign(Rc::new(Binding::new("b", vec![(0, 0)]))),
+                        Instruction::Clear,
+                        fetch("d", vec![]),
+                        Instruction::Assign(Rc::new(Binding::new("c", vec![]))),
+                    ],
+                    **body
+                );
+            }
+            ref instruction => panic!("expected MakeClosure, got {:?}", instruction),
+        }
+    }
+
+    #[test]
+    fn resolves_pins_in_the_scope_of_the_rescue() {
+        let (_, instructions, _) = compile(&parse_statements(
+            "let id = 1\nlet f = fn(id) do\nrescue({ \"id\" => ^id } as x) do\nend\nend",
+        ));
+
+        match instructions[4] {
+            Instruction::MakeClosure(_, _, ref body, _) => match body[1] {
+                Instruction::Rescue(ref pattern, _, _, _, _) => assert_eq!(
+                    p_as(
+                        p_map(vec![(p_string("id"), p_pin("id", vec![(0, 0), (1, 0)]))]),
+                        "x"
+                    ),
+                    **pattern
+                ),
+                ref instruction => panic!("expected Rescue, got {:?}", instruction),
+            },
+            ref instruction => panic!("expected MakeClosure, got {:?}", instruction),
+        }
+    }
+
+    #[test]
+    fn compiles_guards_separately() {
+        assert_eq!(
+            compile_statement(&s_rescue_when(
+                p_ident("x"),
+                e_binop(">", e_identifier("x"), e_literal(l_number(1, 1))),
+                vec![]
+            )),
+            vec![Instruction::Rescue(
+                Rc::new(p_ident("x")),
+                Some(Rc::new(Guard {
+                    instructions: Rc::new(vec![
+                        fetch("x", vec![(0, 0)]),
+                        Instruction::Push(l_number(1, 1)),
+                        Instruction::BinOp(Op::Gt),
+                    ]),
+                    source_map: Rc::new(SourceMap::new()),
+                })),
+                Rc::new(vec!["x".to_owned()]),
+                Rc::new(vec![]),
+                Rc::new(SourceMap::new()),
+            )]
+        );
+    }
+
+    #[test]
+    fn compiles_reraise_and_propagate_statements() {
+        assert_eq!(
+            compile_statement(&s_reraise(None)),
+            vec![Instruction::Rescued, Instruction::Reraise]
+        );
+        assert_eq!(
+            compile_statement(&s_propagate(Some(e_identifier("a")))),
+            vec![fetch("a", vec![]), Instruction::Propagate]
+        );
+    }
+
+    #[test]
+    fn maps_instructions_to_the_nodes_they_were_compiled_from() {
+        let (_, instructions, source_map) = compile(&parse_statements("let a = b + 1\nraise(a)"));
+        let spans = (0..instructions.len())
+            .map(|index| source_map.span_at(index).map(|span| (span.start, span.end)))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            vec![
+                Some((0, 14)),
+                Some((8, 10)),
+                Some((12, 14)),
+                Some((8, 14)),
+                Some((0, 14)),
+                Some((14, 22)),
+                Some((20, 21)),
+                Some((14, 22)),
+            ],
+            spans
+        );
+        assert_eq!(None, source_map.span_at(instructions.len()).map(|span| span.start));
+    }
+
     #[test]
     fn compiles_identifier_expressions() {
         assert_eq!(
             compile_expression(&e_identifier("toto")),
-            vec![Instruction::Fetch("toto".to_owned())]
+            vec![fetch("toto", vec![])]
         );
     }
 
@@ -178,7 +671,7 @@ mod test {
                 e_literal(l_string("titi")),
             )),
             vec![
-                Instruction::Fetch("toto".to_owned()),
+                fetch("toto", vec![]),
                 Instruction::Push(l_string("titi")),
                 Instruction::IndexAccess,
             ]
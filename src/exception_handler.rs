@@ -1,58 +1,258 @@
 use ast::*;
+use binding_map::BindingMap;
 use closure::Closure;
+use instructions::InstructionSequence;
+use number::Number;
+use source_map::SourceMap;
 use value::Value;
 
-use num::rational::BigRational;
+use num::rational::Ratio;
+use num::BigInt;
 use regex::Regex;
+use std::cell::RefCell;
 use std::collections::btree_map::Entry;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::rc::Rc;
+use std::str::FromStr;
 
 pub type MatchedBindings = Option<BTreeMap<String, Value>>;
 
+// Map patterns with this key get the trace of the raise bound to its value,
+// unless the raised map has such a key itself.
+pub const TRACE_KEY: &str = "__trace__";
+
+// Which handlers a raise can reach.
+#[derive(Clone, Copy, Eq, Debug, PartialEq)]
+pub enum HandlerLookup {
+    // Handlers of the frames that led to the raise: the functions that called
+    // the raising one, and the frames whose handlers rescued the raises that
+    // led to it.
+    Callers,
+    // Handlers of every frame, newest first, wherever they were registered.
+    // This is how the language used to behave.
+    Global,
+}
+
+// What a value needs for a handler to have a chance of matching it, which is
+// enough to look handlers up without trying each of them.
+#[derive(Clone, Eq, Debug, PartialEq)]
+pub enum Requirement {
+    // Being equal to this value.
+    Value(Value),
+    // Being a map with this key, and this value for it when there's one.
+    Entry(Value, Option<Value>),
+    Map,
+    // Nothing that can be told without matching.
+    Anything,
+}
+
+impl FromStr for HandlerLookup {
+    type Err = String;
+
+    fn from_str(lookup: &str) -> Result<HandlerLookup, String> {
+        match lookup {
+            "callers" => Ok(HandlerLookup::Callers),
+            "global" => Ok(HandlerLookup::Global),
+            _ => Err(format!(
+                "unknown handler lookup {:?}, expected callers or global",
+                lookup
+            )),
+        }
+    }
+}
+
+// An expression the pattern's bindings must also satisfy, compiled to run in
+// the scope of the rescue block.
+#[derive(Clone, Eq, Debug, PartialEq, PartialOrd, Ord)]
+pub struct Guard {
+    pub instructions: Rc<InstructionSequence>,
+    pub source_map: Rc<SourceMap>,
+}
+
 #[derive(Clone, Eq, Debug, PartialEq)]
 pub struct ExceptionHandler {
     pub closure: Closure,
+    pub guard: Option<Rc<Guard>>,
     pattern: Rc<Pattern>,
+    wants_trace: bool,
 }
 
 impl ExceptionHandler {
     pub fn new(pattern: Rc<Pattern>, closure: Closure) -> ExceptionHandler {
+        let wants_trace = ExceptionHandler::pattern_wants_trace(&pattern);
+
         ExceptionHandler {
             pattern: pattern,
             closure: closure,
+            guard: None,
+            wants_trace,
+        }
+    }
+
+    // Whether every value the other handler matches is matched by this one.
+    // Guarded handlers can turn down any value, and pinned names can have
+    // different values for each handler, so neither ever shadows others.
+    pub fn shadows(&self, other: &ExceptionHandler) -> bool {
+        self.guard.is_none()
+            && !self.pattern.has_pins()
+            && (Rc::ptr_eq(&self.pattern, &other.pattern) || self.pattern == other.pattern)
+    }
+
+    pub fn wants_trace(&self) -> bool {
+        self.wants_trace
+    }
+
+    // Only the raised value gets the trace, so maps nested in it don't count.
+    fn pattern_wants_trace(pattern: &Pattern) -> bool {
+        match pattern {
+            Pattern::Map(pairs, _, _) => pairs.iter().any(|(key, _)| match key {
+                Pattern::CharString(key, _) => key == TRACE_KEY,
+                _ => false,
+            }),
+            Pattern::Alternatives(alternatives, _) => alternatives
+                .iter()
+                .any(ExceptionHandler::pattern_wants_trace),
+            Pattern::As(pattern, _, _) | Pattern::Type(_, pattern, _) => {
+                ExceptionHandler::pattern_wants_trace(pattern)
+            }
+            _ => false,
+        }
+    }
+
+    pub fn requirement(&self) -> Requirement {
+        ExceptionHandler::pattern_requirement(&self.pattern)
+    }
+
+    // The trace key is left out, as values only get it once they're matched.
+    // Of the other literal keys, one with a literal value narrows things down
+    // the most.
+    fn pattern_requirement(pattern: &Pattern) -> Requirement {
+        match pattern {
+            Pattern::Map(pairs, _, _) => {
+                let entries: Vec<_> = pairs
+                    .iter()
+                    .filter_map(
+                        |(key, value)| match ExceptionHandler::pattern_key_to_value(key) {
+                            Some(Value::CharString(ref key)) if key == TRACE_KEY => None,
+                            key => {
+                                key.map(|key| (key, ExceptionHandler::pattern_key_to_value(value)))
+                            }
+                        },
+                    )
+                    .collect();
+                let entry = entries
+                    .iter()
+                    .find(|&(_, value)| value.is_some())
+                    .or(entries.first());
+                match entry {
+                    Some((key, value)) => Requirement::Entry(key.clone(), value.clone()),
+                    None => Requirement::Map,
+                }
+            }
+            Pattern::As(pattern, _, _) | Pattern::Type(_, pattern, _) => {
+                ExceptionHandler::pattern_requirement(pattern)
+            }
+            _ => match ExceptionHandler::pattern_key_to_value(pattern) {
+                Some(value) => Requirement::Value(value),
+                None => Requirement::Anything,
+            },
         }
     }
+
+    // The raised value as seen by handlers that want the trace.
+    pub fn with_trace(value: &Value, trace: Value) -> Value {
+        match value {
+            Value::Map(map) => {
+                let trace_key = Value::CharString(TRACE_KEY.to_owned());
+                if map.borrow().contains_key(&trace_key) {
+                    return value.clone();
+                }
+                let mut map = map.borrow().clone();
+                map.insert(trace_key, trace);
+                Value::Map(Rc::new(RefCell::new(map)))
+            }
+            _ => value.clone(),
+        }
+    }
+
     pub fn matches(&self, value: Value) -> MatchedBindings {
-        ExceptionHandler::match_pattern(&*self.pattern, &value)
+        ExceptionHandler::match_pattern(&self.pattern, &value, &self.closure.parent_bindings)
     }
 
-    fn match_pattern(pattern: &Pattern, value: &Value) -> MatchedBindings {
+    // Pinned bindings are looked up in `env`, the bindings the rescue block
+    // was registered with.
+    fn match_pattern(pattern: &Pattern, value: &Value, env: &BindingMap) -> MatchedBindings {
         match pattern {
-            &Pattern::Number(ref ratio) => ExceptionHandler::match_number(ratio, value),
-            &Pattern::CharString(ref string) => ExceptionHandler::match_string(string, value),
-            &Pattern::Boolean(bool) => ExceptionHandler::match_bool(bool, value),
-            &Pattern::Map(ref pairs) => ExceptionHandler::match_map(pairs, value),
-            &Pattern::Identifier(ref name) => ExceptionHandler::match_identifier(name, value),
-            &Pattern::StringMatch(ref bindings, ref matcher) => {
+            Pattern::Number(number, _) => ExceptionHandler::match_number(number, value),
+            Pattern::CharString(string, _) => ExceptionHandler::match_string(string, value),
+            Pattern::Boolean(bool, _) => ExceptionHandler::match_bool(*bool, value),
+            Pattern::Map(pairs, exact, _) => {
+                ExceptionHandler::match_map(pairs, *exact, value, env)
+            }
+            Pattern::List(items, tail, _) => {
+                ExceptionHandler::match_list(items, tail, value, env)
+            }
+            Pattern::Identifier(name, _) => ExceptionHandler::match_identifier(name, value),
+            Pattern::StringMatch(bindings, matcher, _) => {
                 ExceptionHandler::match_string_match(bindings, &matcher.regex, value)
             }
+            Pattern::Wildcard(_) => Some(BTreeMap::new()),
+            Pattern::Pin(binding, _) => match env.get(binding) {
+                Some(ref pinned) if pinned == value => Some(BTreeMap::new()),
+                _ => None,
+            },
+            Pattern::Type(value_type, pattern, _) => {
+                match ExceptionHandler::has_type(*value_type, value) {
+                    true => ExceptionHandler::match_pattern(pattern, value, env),
+                    false => None,
+                }
+            }
+            Pattern::Range(from, to, _) => match value {
+                Value::Number(number) if from <= number && number <= to => {
+                    Some(BTreeMap::new())
+                }
+                _ => None,
+            },
+            Pattern::Alternatives(alternatives, _) => alternatives
+                .iter()
+                .filter_map(|alternative| ExceptionHandler::match_pattern(alternative, value, env))
+                .next(),
+            Pattern::As(pattern, name, _) => {
+                let mut bindings = ExceptionHandler::match_pattern(pattern, value, env)?;
+                let own_bindings = ExceptionHandler::match_identifier(name, value).unwrap();
+                match ExceptionHandler::merge_bindings(&mut bindings, own_bindings) {
+                    true => Some(bindings),
+                    false => None,
+                }
+            }
         }
     }
 
-    fn match_string_match(bindings: &Vec<String>, regex: &Regex, value: &Value) -> MatchedBindings {
+    fn match_string_match(
+        bindings: &[Option<(String, ValueType)>],
+        regex: &Regex,
+        value: &Value,
+    ) -> MatchedBindings {
         if let &Value::CharString(ref char_str) = value {
             if let Some(mat) = regex.captures(char_str) {
-                let results = bindings
-                    .iter()
-                    .enumerate()
-                    .map(|(index, ref name)| {
-                        (
-                            name.to_string(),
-                            Value::CharString(mat.get(index + 1).unwrap().as_str().to_owned()),
-                        )
-                    })
-                    .collect();
+                let mut results = BTreeMap::new();
+                for (index, binding) in bindings.iter().enumerate() {
+                    // Groups of explicit regexes can be left out of the match.
+                    let (name, capture) = match (binding, mat.get(index + 1)) {
+                        (&Some((ref name, value_type)), Some(capture)) => (
+                            name,
+                            ExceptionHandler::capture_value(value_type, capture.as_str()),
+                        ),
+                        _ => continue,
+                    };
+                    let binding = vec![(name.to_string(), capture)];
+                    if !ExceptionHandler::merge_bindings(
+                        &mut results,
+                        binding.into_iter().collect(),
+                    ) {
+                        return None;
+                    }
+                }
 
                 Some(results)
             } else {
@@ -63,9 +263,30 @@ impl ExceptionHandler {
         }
     }
 
-    fn match_number(ratio: &BigRational, value: &Value) -> MatchedBindings {
+    fn capture_value(value_type: ValueType, capture: &str) -> Value {
+        match value_type {
+            ValueType::Number => Value::Number(Number::from(Ratio::from_integer(
+                BigInt::parse_bytes(capture.as_bytes(), 10).unwrap(),
+            ))),
+            _ => Value::CharString(capture.to_owned()),
+        }
+    }
+
+    fn has_type(value_type: ValueType, value: &Value) -> bool {
+        matches!(
+            (value_type, value),
+            (ValueType::Number, &Value::Number(_))
+                | (ValueType::CharString, &Value::CharString(_))
+                | (ValueType::Boolean, &Value::Boolean(_))
+                | (ValueType::Map, &Value::Map(_))
+                | (ValueType::List, &Value::List(_))
+                | (ValueType::Function, &Value::Closure(..))
+        )
+    }
+
+    fn match_number(number: &Number, value: &Value) -> MatchedBindings {
         match value {
-            &Value::Number(ref number) => match ratio.eq(number) {
+            Value::Number(other_number) => match number.eq(other_number) {
                 true => Some(BTreeMap::new()),
                 _ => None,
             },
@@ -75,7 +296,7 @@ impl ExceptionHandler {
 
     fn match_string(string: &String, value: &Value) -> MatchedBindings {
         match value {
-            &Value::CharString(ref str) => match str.eq(string) {
+            Value::CharString(str) => match str.eq(string) {
                 true => Some(BTreeMap::new()),
                 _ => None,
             },
@@ -85,7 +306,7 @@ impl ExceptionHandler {
 
     fn match_bool(b: bool, value: &Value) -> MatchedBindings {
         match value {
-            &Value::Boolean(other_bool) => match b == other_bool {
+            Value::Boolean(other_bool) => match b == *other_bool {
                 true => Some(BTreeMap::new()),
                 _ => None,
             },
@@ -93,33 +314,103 @@ impl ExceptionHandler {
         }
     }
 
-    fn match_map(pairs: &Vec<(Pattern, Pattern)>, value: &Value) -> MatchedBindings {
+    // Literal keys are looked up first. Other keys are patterns that take
+    // the first entry, in key order, that they match along with its value and
+    // that no other key took.
+    fn match_map(
+        pairs: &[(Pattern, Pattern)],
+        exact: bool,
+        value: &Value,
+        env: &BindingMap,
+    ) -> MatchedBindings {
+        let map = match value {
+            Value::Map(map) => map.borrow(),
+            _ => return None,
+        };
+
+        let mut bindings: BTreeMap<String, Value> = BTreeMap::new();
+        let mut taken = BTreeSet::new();
+        let (literal_pairs, pattern_pairs): (Vec<_>, Vec<_>) = pairs
+            .iter()
+            .partition(|&(key, _)| ExceptionHandler::pattern_key_to_value(key).is_some());
+
+        for (key, pattern_value) in literal_pairs.into_iter() {
+            let key = ExceptionHandler::pattern_key_to_value(key).unwrap();
+            let nested_bindings = map
+                .get(&key)
+                .and_then(|value| ExceptionHandler::match_pattern(pattern_value, value, env))?;
+
+            if !ExceptionHandler::merge_bindings(&mut bindings, nested_bindings) {
+                return None;
+            }
+            taken.insert(key);
+        }
+
+        for (key_pattern, pattern_value) in pattern_pairs.into_iter() {
+            let entry = map
+                .iter()
+                .filter(|&(key, _)| !taken.contains(key))
+                .filter_map(|(key, value)| {
+                    let mut entry_bindings = bindings.clone();
+                    let key_bindings = ExceptionHandler::match_pattern(key_pattern, key, env)?;
+                    let value_bindings =
+                        ExceptionHandler::match_pattern(pattern_value, value, env)?;
+                    match ExceptionHandler::merge_bindings(&mut entry_bindings, key_bindings)
+                        && ExceptionHandler::merge_bindings(&mut entry_bindings, value_bindings)
+                    {
+                        true => Some((key, entry_bindings)),
+                        false => None,
+                    }
+                })
+                .next();
+
+            let (key, entry_bindings) = entry?;
+            bindings = entry_bindings;
+            taken.insert(key.clone());
+        }
+
+        match !exact || taken.len() == map.len() {
+            true => Some(bindings),
+            false => None,
+        }
+    }
+
+    fn match_list(
+        items: &[Pattern],
+        tail: &Option<String>,
+        value: &Value,
+        env: &BindingMap,
+    ) -> MatchedBindings {
         match value {
-            &Value::Map(ref btreemap) => {
-                let mut bindings: BTreeMap<String, Value> = BTreeMap::new();
-                for &(ref key, ref pattern_value) in pairs.iter() {
-                    let key_as_value = ExceptionHandler::pattern_key_to_value(key);
+            Value::List(list) => {
+                let list = list.borrow();
+                let length_matches = match *tail {
+                    Some(_) => list.len() >= items.len(),
+                    None => list.len() == items.len(),
+                };
+                if !length_matches {
+                    return None;
+                }
 
-                    let maybe_nested_bindings = btreemap
-                        .borrow()
-                        .get(&Rc::new(key_as_value))
-                        .and_then(|value| ExceptionHandler::match_pattern(pattern_value, value));
+                let mut bindings: BTreeMap<String, Value> = BTreeMap::new();
+                for (pattern, value) in items.iter().zip(list.iter()) {
+                    let nested_bindings = ExceptionHandler::match_pattern(pattern, value, env)?;
 
-                    if let None = maybe_nested_bindings {
+                    if !ExceptionHandler::merge_bindings(&mut bindings, nested_bindings) {
                         return None;
                     }
+                }
 
-                    for (key, value) in maybe_nested_bindings.unwrap().iter() {
-                        match bindings.entry(key.to_owned()) {
-                            Entry::Occupied(entry) => {
-                                if entry.get() != value {
-                                    return None;
-                                }
-                            }
-                            Entry::Vacant(v) => {
-                                v.insert(value.to_owned());
-                            }
-                        }
+                if let Some(name) = tail {
+                    let rest = list[items.len()..].to_vec();
+                    let tail_bindings = ExceptionHandler::match_identifier(
+                        name,
+                        &Value::List(Rc::new(RefCell::new(rest))),
+                    )
+                    .unwrap();
+
+                    if !ExceptionHandler::merge_bindings(&mut bindings, tail_bindings) {
+                        return None;
                     }
                 }
                 Some(bindings)
@@ -128,17 +419,38 @@ impl ExceptionHandler {
         }
     }
 
+    // Adds the nested bindings to the bindings, failing if a name is bound
+    // to two different values.
+    fn merge_bindings(
+        bindings: &mut BTreeMap<String, Value>,
+        nested_bindings: BTreeMap<String, Value>,
+    ) -> bool {
+        for (key, value) in nested_bindings.into_iter() {
+            match bindings.entry(key) {
+                Entry::Occupied(entry) => {
+                    if *entry.get() != value {
+                        return false;
+                    }
+                }
+                Entry::Vacant(v) => {
+                    v.insert(value);
+                }
+            }
+        }
+        true
+    }
+
     fn match_identifier(name: &String, value: &Value) -> MatchedBindings {
         let bindings: BTreeMap<_, _> = vec![(name.to_owned(), value.clone())].into_iter().collect();
         Some(bindings)
     }
 
-    fn pattern_key_to_value(pattern: &Pattern) -> Value {
+    fn pattern_key_to_value(pattern: &Pattern) -> Option<Value> {
         match pattern {
-            &Pattern::Number(ref ratio) => Value::Number(ratio.to_owned()),
-            &Pattern::CharString(ref str) => Value::CharString(str.to_owned()),
-            &Pattern::Boolean(bool) => Value::Boolean(bool),
-            ref pat => panic!("pattern cannot be used as key ({:?})", pat),
+            Pattern::Number(number, _) => Some(Value::Number(number.clone())),
+            Pattern::CharString(str, _) => Some(Value::CharString(str.to_owned())),
+            Pattern::Boolean(bool, _) => Some(Value::Boolean(*bool)),
+            _ => None,
         }
     }
 }
@@ -149,10 +461,17 @@ mod test {
     use std::rc::Rc;
     use test_helpers::*;
 
+    #[test]
+    fn parses_handler_lookups() {
+        assert_eq!(Ok(HandlerLookup::Callers), "callers".parse());
+        assert_eq!(Ok(HandlerLookup::Global), "global".parse());
+        assert_err!("nearest".parse::<HandlerLookup>());
+    }
+
     #[test]
     fn matches_numbers() {
         let handler = ExceptionHandler::new(
-            Rc::new(Pattern::Number(build_ratio(1, 1))),
+            Rc::new(p_number(1, 1)),
             Closure::blank(),
         );
         assert_eq!(Some(BTreeMap::new()), handler.matches(v_number(1, 1)));
@@ -163,7 +482,7 @@ mod test {
     #[test]
     fn matches_strings() {
         let handler = ExceptionHandler::new(
-            Rc::new(Pattern::CharString("toto".to_owned())),
+            Rc::new(p_string("toto")),
             Closure::blank(),
         );
         assert_eq!(Some(BTreeMap::new()), handler.matches(v_string("toto")));
@@ -173,7 +492,7 @@ mod test {
 
     #[test]
     fn matches_bools() {
-        let handler = ExceptionHandler::new(Rc::new(Pattern::Boolean(false)), Closure::blank());
+        let handler = ExceptionHandler::new(Rc::new(p_bool(false)), Closure::blank());
         assert_eq!(Some(BTreeMap::new()), handler.matches(v_bool(false)));
         assert_eq!(None, handler.matches(v_bool(true)));
         assert_eq!(None, handler.matches(v_string("toto")));
@@ -182,9 +501,9 @@ mod test {
     #[test]
     fn matches_simple_map() {
         let handler = ExceptionHandler::new(
-            Rc::new(Pattern::Map(vec![(
-                Pattern::Number(build_ratio(1, 1)),
-                Pattern::Identifier("toto".to_owned()),
+            Rc::new(p_map(vec![(
+                p_number(1, 1),
+                p_ident("toto"),
             )])),
             Closure::blank(),
         );
@@ -206,14 +525,14 @@ mod test {
     #[test]
     fn matches_maps_with_multiple_bindings_of_equal_values() {
         let handler = ExceptionHandler::new(
-            Rc::new(Pattern::Map(vec![
+            Rc::new(p_map(vec![
                 (
-                    Pattern::Number(build_ratio(1, 1)),
-                    Pattern::Identifier("toto".to_owned()),
+                    p_number(1, 1),
+                    p_ident("toto"),
                 ),
                 (
-                    Pattern::Number(build_ratio(2, 1)),
-                    Pattern::Identifier("toto".to_owned()),
+                    p_number(2, 1),
+                    p_ident("toto"),
                 ),
             ])),
             Closure::blank(),
@@ -240,11 +559,11 @@ mod test {
 
     #[test]
     fn matches_recursive_maps() {
-        let pattern = Pattern::Map(vec![(
-            Pattern::Number(build_ratio(1, 1)),
-            Pattern::Map(vec![(
-                Pattern::Number(build_ratio(2, 1)),
-                Pattern::Identifier("toto".to_owned()),
+        let pattern = p_map(vec![(
+            p_number(1, 1),
+            p_map(vec![(
+                p_number(2, 1),
+                p_ident("toto"),
             )]),
         )]);
         let handler = ExceptionHandler::new(Rc::new(pattern), Closure::blank());
@@ -261,10 +580,113 @@ mod test {
         );
     }
 
+    #[test]
+    fn adds_traces_to_maps_for_handlers_that_want_them() {
+        let handler = ExceptionHandler::new(
+            Rc::new(p_map(vec![(p_string(TRACE_KEY), p_ident("t"))])),
+            Closure::blank(),
+        );
+        assert!(handler.wants_trace());
+        assert!(!ExceptionHandler::new(Rc::new(p_ident("t")), Closure::blank()).wants_trace());
+        let trace_map = || p_map(vec![(p_string(TRACE_KEY), p_ident("t"))]);
+        for pattern in &[
+            p_as(trace_map(), "v"),
+            p_type(ValueType::Map, trace_map()),
+            p_alternatives(vec![p_number(1, 1), trace_map()]),
+        ] {
+            let handler = ExceptionHandler::new(Rc::new(pattern.clone()), Closure::blank());
+            assert!(handler.wants_trace());
+        }
+        let nested = p_map(vec![(p_string("inner"), trace_map())]);
+        assert!(!ExceptionHandler::new(Rc::new(nested), Closure::blank()).wants_trace());
+
+        let value = v_map(vec![(v_string("a"), v_number(1, 1))]);
+        assert_eq!(
+            v_map(vec![
+                (v_string("a"), v_number(1, 1)),
+                (v_string(TRACE_KEY), v_list(vec![])),
+            ]),
+            ExceptionHandler::with_trace(&value, v_list(vec![]))
+        );
+        assert_eq!(v_map(vec![(v_string("a"), v_number(1, 1))]), value);
+
+        let value = v_map(vec![(v_string(TRACE_KEY), v_number(1, 1))]);
+        assert_eq!(value, ExceptionHandler::with_trace(&value, v_list(vec![])));
+        assert_eq!(
+            v_number(1, 1),
+            ExceptionHandler::with_trace(&v_number(1, 1), v_list(vec![]))
+        );
+    }
+
+    #[test]
+    fn matches_lists() {
+        let handler = ExceptionHandler::new(
+            Rc::new(p_list(vec![p_number(1, 1), p_ident("toto")], None)),
+            Closure::blank(),
+        );
+        assert_eq!(
+            Some(
+                vec![("toto".to_owned(), v_string("titi"))]
+                    .into_iter()
+                    .collect(),
+            ),
+            handler.matches(v_list(vec![v_number(1, 1), v_string("titi")]))
+        );
+        assert_eq!(
+            None,
+            handler.matches(v_list(vec![v_number(2, 1), v_string("titi")]))
+        );
+        assert_eq!(
+            None,
+            handler.matches(v_list(vec![v_number(1, 1), v_string("titi"), v_bool(true)]))
+        );
+        assert_eq!(None, handler.matches(v_list(vec![v_number(1, 1)])));
+        assert_eq!(None, handler.matches(v_map(vec![])));
+    }
+
+    #[test]
+    fn matches_lists_with_tails() {
+        let handler = ExceptionHandler::new(
+            Rc::new(p_list(vec![p_ident("head")], Some("tail"))),
+            Closure::blank(),
+        );
+        assert_eq!(
+            Some(
+                vec![
+                    ("head".to_owned(), v_number(1, 1)),
+                    ("tail".to_owned(), v_list(vec![v_number(2, 1), v_number(3, 1)])),
+                ].into_iter()
+                    .collect(),
+            ),
+            handler.matches(v_list(vec![v_number(1, 1), v_number(2, 1), v_number(3, 1)]))
+        );
+        assert_eq!(
+            Some(
+                vec![
+                    ("head".to_owned(), v_number(1, 1)),
+                    ("tail".to_owned(), v_list(vec![])),
+                ].into_iter()
+                    .collect(),
+            ),
+            handler.matches(v_list(vec![v_number(1, 1)]))
+        );
+        assert_eq!(None, handler.matches(v_list(vec![])));
+    }
+
+    #[test]
+    fn matches_lists_with_repeated_bindings() {
+        let handler = ExceptionHandler::new(
+            Rc::new(p_list(vec![p_ident("x"), p_ident("x")], None)),
+            Closure::blank(),
+        );
+        assert!(handler.matches(v_list(vec![v_number(1, 1), v_number(1, 1)])).is_some());
+        assert_eq!(None, handler.matches(v_list(vec![v_number(1, 1), v_number(2, 1)])));
+    }
+
     #[test]
     fn matches_identifier() {
         let handler = ExceptionHandler::new(
-            Rc::new(Pattern::Identifier("toto".to_owned())),
+            Rc::new(p_ident("toto")),
             Closure::blank(),
         );
         assert_eq!(
@@ -318,4 +740,293 @@ mod test {
             handler.matches(v_string("hello 1 world"))
         );
     }
+
+    fn bindings(pairs: Vec<(&str, Value)>) -> MatchedBindings {
+        Some(
+            pairs
+                .into_iter()
+                .map(|(name, value)| (name.to_owned(), value))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn matches_string_match_with_repeated_bindings() {
+        let handler = ExceptionHandler::new(
+            Rc::new(p_string_match(vec!["a", "a"], r#"(?s)\A(.*?)-(.*?)\z"#)),
+            Closure::blank(),
+        );
+        assert_eq!(
+            bindings(vec![("a", v_string("x"))]),
+            handler.matches(v_string("x-x"))
+        );
+        assert_eq!(None, handler.matches(v_string("x-y")));
+    }
+
+    #[test]
+    fn matches_wildcards() {
+        let handler = ExceptionHandler::new(
+            Rc::new(p_map(vec![(p_string("a"), p_wildcard())])),
+            Closure::blank(),
+        );
+        assert_eq!(
+            bindings(vec![]),
+            handler.matches(v_map(vec![(v_string("a"), v_number(1, 1))]))
+        );
+        assert_eq!(None, handler.matches(v_map(vec![])));
+    }
+
+    #[test]
+    fn matches_alternatives() {
+        let handler = ExceptionHandler::new(
+            Rc::new(p_alternatives(vec![
+                p_map(vec![(p_string("ok"), p_ident("a"))]),
+                p_list(vec![p_ident("a")], None),
+            ])),
+            Closure::blank(),
+        );
+        assert_eq!(
+            bindings(vec![("a", v_number(1, 1))]),
+            handler.matches(v_map(vec![(v_string("ok"), v_number(1, 1))]))
+        );
+        assert_eq!(
+            bindings(vec![("a", v_number(2, 1))]),
+            handler.matches(v_list(vec![v_number(2, 1)]))
+        );
+        assert_eq!(None, handler.matches(v_number(1, 1)));
+    }
+
+    #[test]
+    fn matches_as_bindings() {
+        let handler = ExceptionHandler::new(
+            Rc::new(p_as(p_map(vec![(p_string("a"), p_ident("a"))]), "all")),
+            Closure::blank(),
+        );
+        let value = v_map(vec![(v_string("a"), v_number(1, 1))]);
+        assert_eq!(
+            bindings(vec![("a", v_number(1, 1)), ("all", value.clone())]),
+            handler.matches(value)
+        );
+
+        let handler = ExceptionHandler::new(
+            Rc::new(p_list(vec![p_ident("a"), p_as(p_wildcard(), "a")], None)),
+            Closure::blank(),
+        );
+        assert_eq!(
+            bindings(vec![("a", v_number(1, 1))]),
+            handler.matches(v_list(vec![v_number(1, 1), v_number(1, 1)]))
+        );
+        assert_eq!(
+            None,
+            handler.matches(v_list(vec![v_number(1, 1), v_number(2, 1)]))
+        );
+    }
+
+    #[test]
+    fn matches_exact_maps() {
+        let handler = ExceptionHandler::new(
+            Rc::new(p_exact_map(vec![(p_string("a"), p_ident("a"))])),
+            Closure::blank(),
+        );
+        assert_eq!(
+            bindings(vec![("a", v_number(1, 1))]),
+            handler.matches(v_map(vec![(v_string("a"), v_number(1, 1))]))
+        );
+        assert_eq!(
+            None,
+            handler.matches(v_map(vec![
+                (v_string("a"), v_number(1, 1)),
+                (v_string("b"), v_number(2, 1)),
+            ]))
+        );
+    }
+
+    #[test]
+    fn matches_types() {
+        let handler = ExceptionHandler::new(
+            Rc::new(p_type(ValueType::Number, p_ident("n"))),
+            Closure::blank(),
+        );
+        assert_eq!(
+            bindings(vec![("n", v_number(1, 1))]),
+            handler.matches(v_number(1, 1))
+        );
+        assert_eq!(None, handler.matches(v_string("1")));
+
+        let values = [
+            (ValueType::Number, v_number(1, 1)),
+            (ValueType::CharString, v_string("a")),
+            (ValueType::Boolean, v_bool(true)),
+            (ValueType::Map, v_map(vec![])),
+            (ValueType::List, v_list(vec![])),
+            (ValueType::Function, v_closure(vec![], vec![], None)),
+        ];
+        for &(value_type, _) in values.iter() {
+            let handler =
+                ExceptionHandler::new(Rc::new(p_type(value_type, p_wildcard())), Closure::blank());
+            for &(other_type, ref value) in values.iter() {
+                assert_eq!(
+                    value_type == other_type,
+                    handler.matches(value.clone()).is_some()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn matches_ranges() {
+        let handler = ExceptionHandler::new(Rc::new(p_range(1, 10)), Closure::blank());
+        assert_eq!(bindings(vec![]), handler.matches(v_number(1, 1)));
+        assert_eq!(bindings(vec![]), handler.matches(v_number(19, 2)));
+        assert_eq!(bindings(vec![]), handler.matches(v_number(10, 1)));
+        assert_eq!(None, handler.matches(v_number(21, 2)));
+        assert_eq!(None, handler.matches(v_number(0, 1)));
+        assert_eq!(None, handler.matches(v_string("5")));
+    }
+
+    #[test]
+    fn matches_pinned_values() {
+        let closure = Closure::blank();
+        closure
+            .parent_bindings
+            .local_assign(&"id".to_owned(), v_number(1, 1));
+        let handler = ExceptionHandler::new(
+            Rc::new(p_map(vec![(p_string("id"), p_pin("id", vec![(0, 0)]))])),
+            closure.clone(),
+        );
+        assert_eq!(
+            bindings(vec![]),
+            handler.matches(v_map(vec![(v_string("id"), v_number(1, 1))]))
+        );
+        assert_eq!(
+            None,
+            handler.matches(v_map(vec![(v_string("id"), v_number(2, 1))]))
+        );
+
+        closure
+            .parent_bindings
+            .local_assign(&"id".to_owned(), v_number(2, 1));
+        assert_eq!(
+            bindings(vec![]),
+            handler.matches(v_map(vec![(v_string("id"), v_number(2, 1))]))
+        );
+
+        let handler = ExceptionHandler::new(Rc::new(p_pin("unknown", vec![])), Closure::blank());
+        assert_eq!(None, handler.matches(v_number(1, 1)));
+    }
+
+    #[test]
+    fn matches_typed_string_match() {
+        let handler = ExceptionHandler::new(
+            Rc::new(p_typed_string_match(
+                vec![
+                    Some(("id", ValueType::Number)),
+                    Some(("format", ValueType::CharString)),
+                    None,
+                ],
+                r#"/items/([0-9]+)(?:\.(?P<format>[a-z]+))?( HTTP)?"#,
+            )),
+            Closure::blank(),
+        );
+        assert_eq!(
+            bindings(vec![("id", v_number(12, 1)), ("format", v_string("json"))]),
+            handler.matches(v_string("/items/12.json HTTP"))
+        );
+        assert_eq!(
+            bindings(vec![("id", v_number(12, 1))]),
+            handler.matches(v_string("/items/12"))
+        );
+        assert_eq!(None, handler.matches(v_string("/items/twelve")));
+    }
+
+    #[test]
+    fn matches_maps_with_pattern_keys() {
+        let handler = ExceptionHandler::new(
+            Rc::new(p_map(vec![
+                (p_string("id"), p_ident("id")),
+                (p_string_match(vec!["name"], "x-(.*?)"), p_ident("value")),
+            ])),
+            Closure::blank(),
+        );
+        assert_eq!(
+            bindings(vec![
+                ("id", v_number(1, 1)),
+                ("name", v_string("a")),
+                ("value", v_number(2, 1)),
+            ]),
+            handler.matches(v_map(vec![
+                (v_string("id"), v_number(1, 1)),
+                (v_string("x-b"), v_string("not a number")),
+                (v_string("x-a"), v_number(2, 1)),
+            ]))
+        );
+        assert_eq!(
+            None,
+            handler.matches(v_map(vec![(v_string("id"), v_number(1, 1))]))
+        );
+
+        let handler = ExceptionHandler::new(
+            Rc::new(p_exact_map(vec![
+                (p_string_match(vec!["a"], "x-(.*?)"), p_wildcard()),
+                (p_string_match(vec!["b"], "x-(.*?)"), p_wildcard()),
+            ])),
+            Closure::blank(),
+        );
+        assert_eq!(
+            bindings(vec![("a", v_string("a")), ("b", v_string("b"))]),
+            handler.matches(v_map(vec![
+                (v_string("x-a"), v_number(1, 1)),
+                (v_string("x-b"), v_number(2, 1)),
+            ]))
+        );
+        assert_eq!(
+            None,
+            handler.matches(v_map(vec![
+                (v_string("x-a"), v_number(1, 1)),
+                (v_string("x-b"), v_number(2, 1)),
+                (v_string("y"), v_number(3, 1)),
+            ]))
+        );
+    }
+
+    #[test]
+    fn tells_what_values_need_to_match() {
+        let requirement =
+            |pattern| ExceptionHandler::new(Rc::new(pattern), Closure::blank()).requirement();
+
+        assert_eq!(
+            Requirement::Value(v_number(3, 1)),
+            requirement(p_number(3, 1))
+        );
+        assert_eq!(
+            Requirement::Value(v_string("a")),
+            requirement(p_type(ValueType::CharString, p_string("a")))
+        );
+        assert_eq!(
+            Requirement::Entry(v_string("n"), Some(v_number(0, 1))),
+            requirement(p_map(vec![
+                (p_string("m"), p_ident("m")),
+                (p_string("n"), p_number(0, 1)),
+            ]))
+        );
+        assert_eq!(
+            Requirement::Entry(v_string("n"), None),
+            requirement(p_as(
+                p_map(vec![
+                    (p_string(TRACE_KEY), p_number(0, 1)),
+                    (p_string("n"), p_ident("n")),
+                ]),
+                "value"
+            ))
+        );
+        assert_eq!(
+            Requirement::Map,
+            requirement(p_map(vec![(p_string(TRACE_KEY), p_ident("trace"))]))
+        );
+        assert_eq!(Requirement::Anything, requirement(p_range(1, 3)));
+        assert_eq!(
+            Requirement::Anything,
+            requirement(p_alternatives(vec![p_number(1, 1), p_number(2, 1)]))
+        );
+    }
 }
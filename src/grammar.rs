@@ -2,48 +2,100 @@ include!(concat!(env!("OUT_DIR"), "/exceptional-grammar.rs"));
 
 #[cfg(test)]
 mod test_literals {
+    use super::literal;
     use test_helpers::*;
 
     #[test]
     fn parses_number() {
-        assert_eq!(parse_literal(&"1234"), l_number(1234, 1));
-        assert_eq!(parse_literal(&"0011"), l_number(11, 1));
-        assert_eq!(parse_literal(&"11.5"), l_number(23, 2));
-        assert_eq!(parse_literal(&"11.1234"), l_number(111234, 10000));
-        assert_eq!(parse_literal(&"99.909"), l_number(99909, 1000));
+        assert_eq!(parse_literal("1234"), l_number(1234, 1));
+        assert_eq!(parse_literal("0011"), l_number(11, 1));
+        assert_eq!(parse_literal("11.5"), l_number(23, 2));
+        assert_eq!(parse_literal("11.1234"), l_number(111234, 10000));
+        assert_eq!(parse_literal("99.909"), l_number(99909, 1000));
+        assert_eq!(parse_literal("-12"), l_number(-12, 1));
+        assert_eq!(parse_literal("-0.5"), l_number(-1, 2));
     }
 
     #[test]
     fn parses_strings() {
-        assert_eq!(parse_literal(&"\"\""), l_string(&""));
+        assert_eq!(parse_literal("\"\""), l_string(""));
         assert_eq!(
-            parse_literal(&r#""string with more words""#),
-            l_string(&"string with more words")
+            parse_literal(r#""string with more words""#),
+            l_string("string with more words")
         );
         assert_eq!(
-            parse_literal(&"\"string with\nline breaks\""),
-            l_string(&"string with\nline breaks")
+            parse_literal("\"string with\nline breaks\""),
+            l_string("string with\nline breaks")
         );
         assert_eq!(
-            parse_literal(&r#""string with backslash\nline breaks\rand returns""#),
-            l_string(&"string with backslash\nline breaks\rand returns")
+            parse_literal(r#""string with backslash\nline breaks\rand returns""#),
+            l_string("string with backslash\nline breaks\rand returns")
+        );
+    }
+
+    #[test]
+    fn parses_string_escapes() {
+        assert_eq!(
+            parse_literal(r#""tab\tquote\"backslash\\null\0""#),
+            l_string("tab\tquote\"backslash\\null\0")
+        );
+        assert_eq!(
+            parse_literal(r#""\u{48}\u{e9}\u{1F600}""#),
+            l_string("Hé\u{1F600}")
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_string_escapes() {
+        let error = literal(r#""a\qb""#).unwrap_err();
+        assert_eq!(4, error.column);
+        assert!(error.expected.contains("a valid escape sequence"));
+
+        for invalid in &[r#""\u{}""#, r#""\u{d800}""#, r#""\u{1234567}""#, r#""\u{zz}""#] {
+            assert_err!(literal(invalid));
+        }
+        assert!(literal(r#""\u{110000}""#)
+            .unwrap_err()
+            .expected
+            .contains("a valid unicode escape"));
+    }
+
+    #[test]
+    fn parses_triple_quoted_strings() {
+        assert_eq!(parse_literal(r#""""""""#), l_string(""));
+        assert_eq!(
+            parse_literal("\"\"\"\nHTTP/1.1 200 OK\n\n\"quoted\" \\n\n\"\"\""),
+            l_string("HTTP/1.1 200 OK\n\n\"quoted\" \\n\n")
         );
     }
 
     #[test]
     fn parses_booleans() {
-        assert_eq!(parse_literal(&"true"), l_bool(true));
+        assert_eq!(parse_literal("true"), l_bool(true));
 
-        assert_eq!(parse_literal(&"false"), l_bool(false));
+        assert_eq!(parse_literal("false"), l_bool(false));
+    }
+
+    #[test]
+    fn parses_lists() {
+        assert_eq!(parse_literal("[]"), l_list(vec![]));
+        assert_eq!(
+            parse_literal("[ a, 1, [b] ]"),
+            l_list(vec![
+                e_identifier("a"),
+                e_literal(l_number(1, 1)),
+                e_literal(l_list(vec![e_identifier("b")])),
+            ])
+        )
     }
 
     #[test]
     fn parses_maps() {
         assert_eq!(
-            parse_literal(&"{ a => b, b => c }"),
+            parse_literal("{ a => b, b => c }"),
             l_map(vec![
-                (e_identifier(&"a"), e_identifier(&"b")),
-                (e_identifier(&"b"), e_identifier(&"c")),
+                (e_identifier("a"), e_identifier("b")),
+                (e_identifier("b"), e_identifier("c")),
             ])
         )
     }
@@ -51,18 +103,20 @@ mod test_literals {
 
 #[cfg(test)]
 mod test_expressions {
+    use super::expression;
+    use ast::Expression;
     use test_helpers::*;
 
     #[test]
     fn parses_simple_literal_expressions() {
-        assert_eq!(parse_expression(&"1"), e_literal(l_number(1, 1)));
+        assert_eq!(parse_expression("1"), e_literal(l_number(1, 1)));
 
-        assert_eq!(parse_expression(&"\"\""), e_literal(l_string(&"")));
+        assert_eq!(parse_expression("\"\""), e_literal(l_string("")));
 
         assert_eq!(
-            parse_expression(&"{ \"a\" => 1 }"),
+            parse_expression("{ \"a\" => 1 }"),
             e_literal(l_map(vec![(
-                e_literal(l_string(&"a")),
+                e_literal(l_string("a")),
                 e_literal(l_number(1, 1)),
             )],))
         )
@@ -71,42 +125,288 @@ mod test_expressions {
     #[test]
     fn parses_math() {
         assert_eq!(
-            parse_expression(&"1 + 2 * 3 / 5 - d"),
+            parse_expression("1 + 2 * 3 / 5 - d"),
             e_binop(
-                "+",
-                e_literal(l_number(1, 1)),
+                "-",
                 e_binop(
-                    "-",
+                    "+",
+                    e_literal(l_number(1, 1)),
                     e_binop(
-                        "*",
-                        e_literal(l_number(2, 1)),
-                        e_binop("/", e_literal(l_number(3, 1)), e_literal(l_number(5, 1))),
+                        "/",
+                        e_binop("*", e_literal(l_number(2, 1)), e_literal(l_number(3, 1))),
+                        e_literal(l_number(5, 1)),
                     ),
-                    e_identifier(&"d"),
                 ),
+                e_identifier("d"),
+            )
+        );
+    }
+
+    #[test]
+    fn parses_arithmetic_as_left_associative() {
+        assert_eq!(
+            parse_expression("10 - 3 - 2"),
+            e_binop(
+                "-",
+                e_binop("-", e_literal(l_number(10, 1)), e_literal(l_number(3, 1))),
+                e_literal(l_number(2, 1)),
+            )
+        );
+        assert_eq!(
+            parse_expression("8 / 4 / 2"),
+            e_binop(
+                "/",
+                e_binop("/", e_literal(l_number(8, 1)), e_literal(l_number(4, 1))),
+                e_literal(l_number(2, 1)),
+            )
+        );
+        assert_eq!(
+            parse_expression("a - b + c"),
+            e_binop(
+                "+",
+                e_binop("-", e_identifier("a"), e_identifier("b")),
+                e_identifier("c"),
+            )
+        );
+    }
+
+    #[test]
+    fn parses_modulo_and_floor_division() {
+        assert_eq!(
+            parse_expression("a % b // c * d"),
+            e_binop(
+                "*",
+                e_binop(
+                    "//",
+                    e_binop("%", e_identifier("a"), e_identifier("b")),
+                    e_identifier("c"),
+                ),
+                e_identifier("d"),
+            )
+        );
+    }
+
+    #[test]
+    fn parses_exponentiation_as_right_associative() {
+        assert_eq!(
+            parse_expression("a ** b ** c"),
+            e_binop(
+                "**",
+                e_identifier("a"),
+                e_binop("**", e_identifier("b"), e_identifier("c")),
+            )
+        );
+        assert_eq!(
+            parse_expression("-a ** 2 * b"),
+            e_binop(
+                "*",
+                e_binop(
+                    "**",
+                    e_unaryop("-", e_identifier("a")),
+                    e_literal(l_number(2, 1)),
+                ),
+                e_identifier("b"),
+            )
+        );
+        assert_eq!(
+            parse_expression("2 ** -1"),
+            e_binop("**", e_literal(l_number(2, 1)), e_literal(l_number(-1, 1)))
+        );
+    }
+
+    #[test]
+    fn parses_comparisons_with_lower_precedence_than_arithmetic() {
+        assert_eq!(
+            parse_expression("a + 1 < b * 2"),
+            e_binop(
+                "<",
+                e_binop("+", e_identifier("a"), e_literal(l_number(1, 1))),
+                e_binop("*", e_identifier("b"), e_literal(l_number(2, 1))),
+            )
+        );
+    }
+
+    #[test]
+    fn parses_boolean_operators() {
+        assert_eq!(
+            parse_expression("a != b"),
+            e_binop("!=", e_identifier("a"), e_identifier("b"))
+        );
+        assert_eq!(
+            parse_expression("a or b and c"),
+            e_binop(
+                "or",
+                e_identifier("a"),
+                e_binop("and", e_identifier("b"), e_identifier("c")),
+            )
+        );
+        assert_eq!(
+            parse_expression("a and b or c and d"),
+            e_binop(
+                "or",
+                e_binop("and", e_identifier("a"), e_identifier("b")),
+                e_binop("and", e_identifier("c"), e_identifier("d")),
+            )
+        );
+        assert_eq!(
+            parse_expression("not a == b and c"),
+            e_binop(
+                "and",
+                e_unaryop("not", e_binop("==", e_identifier("a"), e_identifier("b"))),
+                e_identifier("c"),
+            )
+        );
+        assert_eq!(
+            parse_expression("not not a"),
+            e_unaryop("not", e_unaryop("not", e_identifier("a")))
+        );
+    }
+
+    #[test]
+    fn parses_identifiers_starting_with_boolean_operators() {
+        assert_eq!(parse_expression("order"), e_identifier("order"));
+        assert_eq!(parse_expression("android"), e_identifier("android"));
+        assert_eq!(parse_expression("nothing"), e_identifier("nothing"));
+        assert_eq!(
+            parse_expression("origin or note"),
+            e_binop("or", e_identifier("origin"), e_identifier("note"))
+        );
+    }
+
+    #[test]
+    fn rejects_chained_comparisons() {
+        let error = expression("a < b < c").unwrap_err();
+        assert_eq!(9, error.column);
+        assert!(
+            error
+                .expected
+                .contains("comparison operators can't be chained, use parentheses")
+        );
+        assert_eq!(
+            parse_expression("(a < b) == c"),
+            e_binop(
+                "==",
+                e_binop("<", e_identifier("a"), e_identifier("b")),
+                e_identifier("c"),
             )
         );
     }
 
+    #[test]
+    fn parses_grouped_expressions() {
+        assert_eq!(parse_expression("(1)"), e_literal(l_number(1, 1)));
+        assert_eq!(
+            parse_expression("(1 + 2) * 3"),
+            e_binop(
+                "*",
+                e_binop("+", e_literal(l_number(1, 1)), e_literal(l_number(2, 1))),
+                e_literal(l_number(3, 1)),
+            )
+        );
+        assert_eq!(
+            parse_expression("a * (b - c)"),
+            e_binop(
+                "*",
+                e_identifier("a"),
+                e_binop("-", e_identifier("b"), e_identifier("c")),
+            )
+        );
+        assert_eq!(
+            parse_expression("( ( a ) )"),
+            e_identifier("a")
+        );
+        assert_eq!(
+            parse_expression("(a == b) == false"),
+            e_binop(
+                "==",
+                e_binop("==", e_identifier("a"), e_identifier("b")),
+                e_literal(l_bool(false)),
+            )
+        );
+    }
+
+    #[test]
+    fn parses_grouped_expressions_in_maps_and_access() {
+        assert_eq!(
+            parse_expression("{ (a + 1) => (b * 2) }"),
+            e_literal(l_map(vec![(
+                e_binop("+", e_identifier("a"), e_literal(l_number(1, 1))),
+                e_binop("*", e_identifier("b"), e_literal(l_number(2, 1))),
+            )]))
+        );
+        assert_eq!(
+            parse_expression("m[(k + 1)]"),
+            e_index_access(
+                e_identifier("m"),
+                e_binop("+", e_identifier("k"), e_literal(l_number(1, 1))),
+            )
+        );
+        assert_eq!(
+            parse_expression("({ \"a\" => 1 }).a"),
+            e_index_access(
+                e_literal(l_map(vec![(
+                    e_literal(l_string("a")),
+                    e_literal(l_number(1, 1)),
+                )])),
+                e_literal(l_string("a")),
+            )
+        );
+    }
+
+    #[test]
+    fn parses_unary_minus() {
+        assert_eq!(
+            parse_expression("-a"),
+            e_unaryop("-", e_identifier("a"))
+        );
+        assert_eq!(
+            parse_expression("- -a"),
+            e_unaryop("-", e_unaryop("-", e_identifier("a")))
+        );
+        assert_eq!(
+            parse_expression("-a[b] * 2"),
+            e_binop(
+                "*",
+                e_unaryop("-", e_index_access(e_identifier("a"), e_identifier("b"))),
+                e_literal(l_number(2, 1)),
+            )
+        );
+        assert_eq!(
+            parse_expression("-(1 + 2)"),
+            e_unaryop(
+                "-",
+                e_binop("+", e_literal(l_number(1, 1)), e_literal(l_number(2, 1))),
+            )
+        );
+        assert_eq!(
+            parse_expression("1 - -1"),
+            e_binop("-", e_literal(l_number(1, 1)), e_literal(l_number(-1, 1)))
+        );
+        assert_eq!(
+            parse_expression("a -1"),
+            e_binop("-", e_identifier("a"), e_literal(l_number(1, 1)))
+        );
+    }
+
     #[test]
     fn parses_identifiers() {
-        assert_eq!(parse_expression(&"toto"), e_identifier(&"toto"))
+        assert_eq!(parse_expression("toto"), e_identifier("toto"))
     }
 
     #[test]
     fn parses_access() {
         assert_eq!(
-            parse_expression(&"toto[titi][tutu]"),
+            parse_expression("toto[titi][tutu]"),
             e_index_access(
-                e_index_access(e_identifier(&"toto"), e_identifier(&"titi")),
-                e_identifier(&"tutu"),
+                e_index_access(e_identifier("toto"), e_identifier("titi")),
+                e_identifier("tutu"),
             )
         );
         assert_eq!(
-            parse_expression(&"toto.titi.tutu"),
+            parse_expression("toto.titi.tutu"),
             e_index_access(
-                e_index_access(e_identifier(&"toto"), e_literal(l_string(&"titi"))),
-                e_literal(l_string(&"tutu")),
+                e_index_access(e_identifier("toto"), e_literal(l_string("titi"))),
+                e_literal(l_string("tutu")),
             )
         );
     }
@@ -114,7 +414,7 @@ mod test_expressions {
     #[test]
     fn parses_simple_functions() {
         assert_eq!(
-            parse_expression(&"fn() do end"),
+            parse_expression("fn() do end"),
             e_literal(l_function(vec![], vec![]))
         )
     }
@@ -122,7 +422,7 @@ mod test_expressions {
     #[test]
     fn parses_functions_with_args() {
         assert_eq!(
-            parse_expression(&"fn(a, b) do end"),
+            parse_expression("fn(a, b) do end"),
             e_literal(l_function(vec!["a".to_owned(), "b".to_owned()], vec![]))
         )
     }
@@ -130,10 +430,10 @@ mod test_expressions {
     #[test]
     fn prases_functions_with_bodies() {
         assert_eq!(
-            parse_expression(&"fn(a, b) do\nlet c = 1\nend"),
+            parse_expression("fn(a, b) do\nlet c = 1\nend"),
             e_literal(l_function(
                 vec!["a".to_owned(), "b".to_owned()],
-                vec![s_assign(&"c", l_number(1, 1))],
+                vec![s_assign("c", l_number(1, 1))],
             ))
         )
     }
@@ -141,56 +441,71 @@ mod test_expressions {
     #[test]
     fn parses_import() {
         assert_eq!(
-            parse_expression(&"import(\"toto\")"),
-            e_import(e_literal(l_string(&"toto")))
+            parse_expression("import(\"toto\")"),
+            e_import(e_literal(l_string("toto")))
         )
     }
 
     #[test]
     fn parses_comparison_expressions() {
         assert_eq!(
-            parse_expression(&"a == b"),
+            parse_expression("a == b"),
             e_binop("==", e_identifier("a"), e_identifier("b"))
         );
         assert_eq!(
-            parse_expression(&"a >= b"),
+            parse_expression("a >= b"),
             e_binop(">=", e_identifier("a"), e_identifier("b"))
         );
         assert_eq!(
-            parse_expression(&"a > b"),
+            parse_expression("a > b"),
             e_binop(">", e_identifier("a"), e_identifier("b"))
         );
         assert_eq!(
-            parse_expression(&"a <= b"),
+            parse_expression("a <= b"),
             e_binop("<=", e_identifier("a"), e_identifier("b"))
         );
         assert_eq!(
-            parse_expression(&"a < b"),
+            parse_expression("a < b"),
             e_binop("<", e_identifier("a"), e_identifier("b"))
         );
     }
+
+    #[test]
+    fn records_expression_spans() {
+        let expression = parse_expression("a.b + -(1 * c)");
+        assert_eq!((0, 14), (expression.span().start, expression.span().end));
+
+        if let Expression::BinOp(_, ref left, ref right, _) = expression {
+            assert_eq!((0, 4), (left.span().start, left.span().end));
+            assert_eq!((6, 14), (right.span().start, right.span().end));
+        } else {
+            panic!("expected a binary operation, got {:?}", expression);
+        }
+    }
 }
 
 #[cfg(test)]
 mod test_statements {
+    use super::statements;
+    use ast::ValueType;
     use test_helpers::*;
 
     #[test]
     fn parses_assigns() {
         assert_eq!(
-            parse_statements(&"let a = 1"),
-            [s_assign(&"a", l_number(1, 1))]
+            parse_statements("let a = 1"),
+            [s_assign("a", l_number(1, 1))]
         )
     }
 
     #[test]
     fn parses_map_assign() {
         assert_eq!(
-            parse_statements(&"a[b][c] = d"),
+            parse_statements("a[b][c] = d"),
             [s_index_assign(
-                e_index_access(e_identifier(&"a"), e_identifier(&"b")),
-                e_identifier(&"c"),
-                e_identifier(&"d"),
+                e_index_access(e_identifier("a"), e_identifier("b")),
+                e_identifier("c"),
+                e_identifier("d"),
             ),]
         )
     }
@@ -199,12 +514,12 @@ mod test_statements {
     fn parses_calls() {
         assert_eq!(
             parse_statements("a()"),
-            [s_call(e_identifier(&"a"), vec![])]
+            [s_call(e_identifier("a"), vec![])]
         );
         assert_eq!(
-            parse_statements(&"toto.titi()"),
+            parse_statements("toto.titi()"),
             vec![s_call(
-                e_index_access(e_identifier(&"toto"), e_literal(l_string(&"titi"))),
+                e_index_access(e_identifier("toto"), e_literal(l_string("titi"))),
                 vec![],
             )]
         );
@@ -215,8 +530,8 @@ mod test_statements {
         assert_eq!(
             parse_statements("a(1, b)"),
             [s_call(
-                e_identifier(&"a"),
-                vec![e_literal(l_number(1, 1)), e_identifier(&"b")],
+                e_identifier("a"),
+                vec![e_literal(l_number(1, 1)), e_identifier("b")],
             ),]
         )
     }
@@ -229,7 +544,25 @@ mod test_statements {
         ];
         assert_eq!(
             parse_statements("a(1 + 2, fn(x) do end)"),
-            [s_call(e_identifier(&"a"), args)]
+            [s_call(e_identifier("a"), args)]
+        )
+    }
+
+    #[test]
+    fn parses_calls_with_grouped_args() {
+        assert_eq!(
+            parse_statements("a((1 + 2) * 3, (b))"),
+            [s_call(
+                e_identifier("a"),
+                vec![
+                    e_binop(
+                        "*",
+                        e_binop("+", e_literal(l_number(1, 1)), e_literal(l_number(2, 1))),
+                        e_literal(l_number(3, 1)),
+                    ),
+                    e_identifier("b"),
+                ],
+            )]
         )
     }
 
@@ -241,6 +574,23 @@ mod test_statements {
         )
     }
 
+    #[test]
+    fn parses_reraise_and_propagate_statements() {
+        assert_eq!(
+            parse_statements("reraise\nreraise(1)\npropagate\npropagate(a)"),
+            [
+                s_reraise(None),
+                s_reraise(Some(e_literal(l_number(1, 1)))),
+                s_propagate(None),
+                s_propagate(Some(e_identifier("a"))),
+            ]
+        );
+        assert_eq!(
+            parse_statements("let reraised = 1"),
+            [s_assign("reraised", l_number(1, 1))]
+        )
+    }
+
     #[test]
     fn parses_rescue_statements() {
         assert_eq!(
@@ -249,6 +599,109 @@ mod test_statements {
         )
     }
 
+    #[test]
+    fn parses_rescue_statements_with_guards() {
+        assert_eq!(
+            parse_statements("rescue({ \"n\" => n }) when n > 100 do\nend"),
+            [s_rescue_when(
+                p_map(vec![(p_string("n"), p_ident("n"))]),
+                e_binop(">", e_identifier("n"), e_literal(l_number(100, 1))),
+                vec![]
+            )]
+        );
+        assert_eq!(
+            parse_statements("let whenever = 1"),
+            [s_assign("whenever", l_number(1, 1))]
+        )
+    }
+
+    #[test]
+    fn parses_rescue_with_wildcards_alternatives_and_as_bindings() {
+        assert_eq!(
+            parse_statements("rescue({ \"a\" => _, \"b\" => 1 | 2 } as all) do\nend"),
+            [s_rescue(
+                p_as(
+                    p_map(vec![
+                        (p_string("a"), p_wildcard()),
+                        (p_string("b"), p_alternatives(vec![p_number(1, 1), p_number(2, 1)])),
+                    ]),
+                    "all"
+                ),
+                vec![]
+            )]
+        );
+        assert_eq!(
+            parse_statements("rescue([x] | { \"x\" => x } | (_ as x) as z) do\nend"),
+            [s_rescue(
+                p_as(
+                    p_alternatives(vec![
+                        p_list(vec![p_ident("x")], None),
+                        p_map(vec![(p_string("x"), p_ident("x"))]),
+                        p_as(p_wildcard(), "x"),
+                    ]),
+                    "z"
+                ),
+                vec![]
+            )]
+        );
+    }
+
+    #[test]
+    fn rejects_alternatives_binding_different_names() {
+        assert_err!(statements("rescue([x] | [y]) do\nend"));
+    }
+
+    #[test]
+    fn parses_rescue_with_type_and_range_patterns() {
+        assert_eq!(
+            parse_statements("rescue({ \"n\" => number(n), \"f\" => fn(_) }) do\nend"),
+            [s_rescue(
+                p_map(vec![
+                    (p_string("n"), p_type(ValueType::Number, p_ident("n"))),
+                    (p_string("f"), p_type(ValueType::Function, p_wildcard())),
+                ]),
+                vec![]
+            )]
+        );
+        assert_eq!(
+            parse_statements("rescue([1..10, -2 .. 2]) do\nend"),
+            [s_rescue(p_list(vec![p_range(1, 10), p_range(-2, 2)], None), vec![])]
+        );
+        assert_err!(statements("rescue(10..1) do\nend"));
+    }
+
+    #[test]
+    fn parses_rescue_with_pinned_patterns() {
+        assert_eq!(
+            parse_statements("rescue({ \"id\" => ^id, \"reply\" => reply }) do\nend"),
+            [s_rescue(
+                p_map(vec![
+                    (p_string("id"), p_pin("id", vec![])),
+                    (p_string("reply"), p_ident("reply")),
+                ]),
+                vec![]
+            )]
+        );
+    }
+
+    #[test]
+    fn parses_rescue_with_exact_map_patterns() {
+        assert_eq!(
+            parse_statements("rescue({| \"a\" => a |}) do\nend"),
+            [s_rescue(p_exact_map(vec![(p_string("a"), p_ident("a"))]), vec![])]
+        );
+        assert_eq!(
+            parse_statements("rescue({|\"a\" => 1 | 2|}) do\nend"),
+            [s_rescue(
+                p_exact_map(vec![(
+                    p_string("a"),
+                    p_alternatives(vec![p_number(1, 1), p_number(2, 1)])
+                )]),
+                vec![]
+            )]
+        );
+    }
+
     #[test]
     fn parses_rescue_with_number_patterns() {
         assert_eq!(
@@ -257,6 +710,17 @@ mod test_statements {
         )
     }
 
+    #[test]
+    fn parses_rescue_with_negative_number_patterns() {
+        assert_eq!(
+            parse_statements("rescue({ \"delta\" => -1.5 }) do\nend"),
+            [s_rescue(
+                p_map(vec![(p_string("delta"), p_number(-3, 2))]),
+                vec![]
+            )]
+        )
+    }
+
     #[test]
     fn parses_rescue_with_string_patterns() {
         assert_eq!(
@@ -281,6 +745,36 @@ mod test_statements {
         )
     }
 
+    #[test]
+    fn parses_rescue_with_list_patterns() {
+        assert_eq!(
+            parse_statements("rescue([]) do\nend"),
+            [s_rescue(p_list(vec![], None), vec![])]
+        );
+        assert_eq!(
+            parse_statements("rescue([1, x]) do\nend"),
+            [s_rescue(p_list(vec![p_number(1, 1), p_ident("x")], None), vec![])]
+        );
+        assert_eq!(
+            parse_statements("rescue([h, ..t]) do\nend"),
+            [s_rescue(p_list(vec![p_ident("h")], Some("t")), vec![])]
+        );
+        assert_eq!(
+            parse_statements("rescue([..t]) do\nend"),
+            [s_rescue(p_list(vec![], Some("t")), vec![])]
+        );
+        assert_eq!(
+            parse_statements("rescue({ \"items\" => [{ \"a\" => a }, ..rest] }) do\nend"),
+            [s_rescue(
+                p_map(vec![(
+                    p_string("items"),
+                    p_list(vec![p_map(vec![(p_string("a"), p_ident("a"))])], Some("rest")),
+                )]),
+                vec![],
+            )]
+        );
+    }
+
     #[test]
     fn parses_rescue_with_map_patterns() {
         assert_eq!(
@@ -326,4 +820,86 @@ mod test_statements {
             ),]
         );
     }
+
+    #[test]
+    fn parses_rescue_with_escapes_in_string_matching_patterns() {
+        assert_eq!(
+            parse_statements(r#"rescue("say \"" ++ x ++ "\"\n") do end"#),
+            [s_rescue(p_string_match(vec!["x"], "say \"(.*?)\"\n"), vec![])]
+        );
+    }
+
+    #[test]
+    fn parses_rescue_with_typed_string_matching_patterns() {
+        assert_eq!(
+            parse_statements(r#"rescue("GET /items/" ++ digits(id) ++ " " ++ word(_)) do end"#),
+            [s_rescue(
+                p_typed_string_match(
+                    vec![Some(("id", ValueType::Number)), None],
+                    r#"GET /items/([0-9]+) (\w+)"#
+                ),
+                vec![],
+            )]
+        );
+        assert_eq!(
+            parse_statements(r#"rescue(i"get " ++ re"(?P<path>[a-z/]+)(\.\"html\")?") do end"#),
+            [s_rescue(
+                p_typed_string_match(
+                    vec![Some(("path", ValueType::CharString)), None],
+                    r#"(?i:get )(?:([a-z/]+)(\."html")?)"#
+                ),
+                vec![],
+            )]
+        );
+        assert_eq!(
+            parse_statements(r#"rescue(digits(n)) do end"#),
+            [s_rescue(
+                p_typed_string_match(vec![Some(("n", ValueType::Number))], "([0-9]+)"),
+                vec![],
+            )]
+        );
+        assert_err!(statements(r#"rescue(re"[a-") do end"#));
+    }
+
+    #[test]
+    fn parses_rescue_with_regexes_naming_groups_the_same() {
+        assert_eq!(
+            parse_statements(r#"rescue(re"(?P<a>x)" ++ "-" ++ re"(?P<a>x)") do end"#),
+            [s_rescue(
+                p_typed_string_match(
+                    vec![
+                        Some(("a", ValueType::CharString)),
+                        Some(("a", ValueType::CharString)),
+                    ],
+                    r#"(?:(x))\-(?:(x))"#
+                ),
+                vec![],
+            )]
+        );
+    }
+
+    #[test]
+    fn parses_rescue_with_string_matching_patterns_as_map_keys() {
+        assert_eq!(
+            parse_statements(r#"rescue({ "header " ++ name => value }) do end"#),
+            [s_rescue(
+                p_map(vec![(
+                    p_string_match(vec!["name"], "header (.*?)"),
+                    p_ident("value")
+                )]),
+                vec![],
+            )]
+        );
+    }
+
+    #[test]
+    fn records_statement_spans() {
+        let statements = parse_statements("let a = 1\n  raise(a)\nrescue(x) do\nend");
+        let spans = statements
+            .iter()
+            .map(|statement| (statement.span().start, statement.span().end))
+            .collect::<Vec<_>>();
+
+        assert_eq!(vec![(0, 12), (12, 21), (21, 37)], spans);
+    }
 }
@@ -0,0 +1,190 @@
+use exception_handler::{ExceptionHandler, Requirement};
+use std::collections::BTreeMap;
+use std::mem;
+use std::rc::Rc;
+use value::Value;
+
+// The handlers registered in a frame, in order, indexed by what a value needs
+// to have a chance of matching them. A raise only tries the handlers its value
+// could match, still in the order they were registered, so the first one that
+// matches wins as before.
+#[derive(Clone, Eq, Debug, PartialEq, Default)]
+pub struct HandlerTable {
+    handlers: Vec<Rc<ExceptionHandler>>,
+    by_value: BTreeMap<Value, Vec<usize>>,
+    by_key: BTreeMap<Value, KeyHandlers>,
+    maps: Vec<usize>,
+    others: Vec<usize>,
+}
+
+// Handlers of maps with a given key, whatever its value or for a given one.
+#[derive(Clone, Eq, Debug, PartialEq, Default)]
+struct KeyHandlers {
+    any: Vec<usize>,
+    by_value: BTreeMap<Value, Vec<usize>>,
+}
+
+impl HandlerTable {
+    pub fn new() -> HandlerTable {
+        Default::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.handlers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.handlers.is_empty()
+    }
+
+    pub fn position(&self, handler: &Rc<ExceptionHandler>) -> Option<usize> {
+        self.handlers
+            .iter()
+            .position(|registered| Rc::ptr_eq(registered, handler))
+    }
+
+    pub fn push(&mut self, handler: Rc<ExceptionHandler>) {
+        let index = self.handlers.len();
+        match handler.requirement() {
+            Requirement::Value(value) => self.by_value.entry(value).or_default().push(index),
+            Requirement::Entry(key, value) => {
+                let key_handlers = self.by_key.entry(key).or_default();
+                match value {
+                    Some(value) => key_handlers.by_value.entry(value).or_default().push(index),
+                    None => key_handlers.any.push(index),
+                }
+            }
+            Requirement::Map => self.maps.push(index),
+            Requirement::Anything => self.others.push(index),
+        }
+        self.handlers.push(handler);
+    }
+
+    pub fn retain<F>(&mut self, mut keep: F)
+    where
+        F: FnMut(&ExceptionHandler) -> bool,
+    {
+        let handlers = mem::replace(self, HandlerTable::new()).handlers;
+        for handler in handlers.into_iter().filter(|handler| keep(handler)) {
+            self.push(handler);
+        }
+    }
+
+    // The handlers from the `first` one onwards that the value could match, in
+    // the order they were registered.
+    pub fn candidates(&self, value: &Value, first: usize) -> Vec<&Rc<ExceptionHandler>> {
+        let mut indices = self.others.clone();
+        match value {
+            Value::Map(map) => {
+                indices.extend(&self.maps);
+                for (key, value) in map.borrow().iter() {
+                    if let Some(key_handlers) = self.by_key.get(key) {
+                        indices.extend(&key_handlers.any);
+                        if let Some(handlers) = key_handlers.by_value.get(value) {
+                            indices.extend(handlers);
+                        }
+                    }
+                }
+            }
+            _ => {
+                if let Some(handlers) = self.by_value.get(value) {
+                    indices.extend(handlers);
+                }
+            }
+        }
+        indices.retain(|&index| index >= first);
+        indices.sort();
+        indices
+            .into_iter()
+            .map(|index| &self.handlers[index])
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ast::Pattern;
+    use closure::Closure;
+    use exception_handler::TRACE_KEY;
+    use test_helpers::*;
+
+    fn table(patterns: Vec<Pattern>) -> HandlerTable {
+        let mut table = HandlerTable::new();
+        for pattern in patterns {
+            table.push(Rc::new(ExceptionHandler::new(
+                Rc::new(pattern),
+                Closure::blank(),
+            )));
+        }
+        table
+    }
+
+    fn candidates(table: &HandlerTable, value: &Value, first: usize) -> Vec<usize> {
+        table
+            .candidates(value, first)
+            .into_iter()
+            .map(|handler| table.position(handler).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn looks_literals_up() {
+        let table = table(vec![
+            p_number(1, 1),
+            p_string("1"),
+            p_ident("x"),
+            p_number(1, 1),
+            p_map(vec![(p_string("n"), p_ident("n"))]),
+        ]);
+
+        assert_eq!(vec![0, 2, 3], candidates(&table, &v_number(1, 1), 0));
+        assert_eq!(vec![1, 2], candidates(&table, &v_string("1"), 0));
+        assert_eq!(vec![2], candidates(&table, &v_bool(true), 0));
+        assert_eq!(vec![2, 3], candidates(&table, &v_number(1, 1), 2));
+    }
+
+    #[test]
+    fn looks_map_entries_up() {
+        let table = table(vec![
+            p_map(vec![(p_string("n"), p_ident("n"))]),
+            p_map(vec![
+                (p_string("k"), p_number(0, 1)),
+                (p_string("m"), p_ident("m")),
+            ]),
+            p_map(vec![(p_string("k"), p_ident("k"))]),
+            p_map(vec![(p_ident("key"), p_ident("value"))]),
+            p_wildcard(),
+        ]);
+
+        let value = v_map(vec![
+            (v_string("k"), v_number(0, 1)),
+            (v_string("m"), v_number(1, 1)),
+        ]);
+        assert_eq!(vec![1, 2, 3, 4], candidates(&table, &value, 0));
+        let value = v_map(vec![
+            (v_string("k"), v_number(1, 1)),
+            (v_string("n"), v_number(1, 1)),
+        ]);
+        assert_eq!(vec![0, 2, 3, 4], candidates(&table, &value, 0));
+        assert_eq!(vec![3, 4], candidates(&table, &v_map(vec![]), 0));
+        assert_eq!(vec![4], candidates(&table, &v_number(0, 1), 0));
+    }
+
+    #[test]
+    fn keeps_its_index_when_dropping_handlers() {
+        let mut table = table(vec![
+            p_map(vec![(p_string("n"), p_ident("n"))]),
+            p_map(vec![(p_string(TRACE_KEY), p_ident("trace"))]),
+            p_number(1, 1),
+        ]);
+
+        table.retain(|handler| !handler.wants_trace());
+        assert_eq!(2, table.len());
+        assert_eq!(vec![1], candidates(&table, &v_number(1, 1), 0));
+        assert_eq!(
+            vec![0],
+            candidates(&table, &v_map(vec![(v_string("n"), v_number(1, 1))]), 0)
+        );
+    }
+}
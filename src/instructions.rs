@@ -1,4 +1,7 @@
 use ast::{Literal, Pattern};
+use binding_map::{Binding, Layout};
+use exception_handler::Guard;
+use source_map::SourceMap;
 use std::cmp::Ordering;
 use std::fmt;
 use std::mem::transmute;
@@ -9,13 +12,25 @@ use vm::Vm;
 pub enum Op {
     Mul,
     Div,
+    FloorDiv,
+    Mod,
+    Pow,
     Add,
     Sub,
     Eq,
+    NotEq,
     GtEq,
     Gt,
     LtEq,
     Lt,
+    And,
+    Or,
+}
+
+#[derive(Clone, Eq, Debug, PartialEq, PartialOrd, Ord)]
+pub enum UnaryOp {
+    Neg,
+    Not,
 }
 
 pub type NativeCode = *const fn(&mut Vm) -> InstructionSequence;
@@ -68,16 +83,33 @@ impl fmt::Debug for NativeFunction {
 pub enum Instruction {
     Clear,
     Push(Literal),
-    Fetch(String),
-    LocalAssign(String),
-    Assign(String),
+    MakeClosure(
+        Rc<Box<Vec<String>>>,
+        Layout,
+        Rc<InstructionSequence>,
+        Rc<SourceMap>,
+    ),
+    Fetch(Rc<Binding>),
+    LocalAssign(usize),
+    Assign(Rc<Binding>),
     Call(usize),
     MakeMap(usize),
-    Rescue(Rc<Pattern>, Rc<InstructionSequence>),
+    MakeList(usize),
+    Rescue(
+        Rc<Pattern>,
+        Option<Rc<Guard>>,
+        Layout,
+        Rc<InstructionSequence>,
+        Rc<SourceMap>,
+    ),
     IndexAccess,
     IndexAssign,
     Raise,
+    Rescued,
+    Reraise,
+    Propagate,
     BinOp(Op),
+    UnaryOp(UnaryOp),
     Import,
     Native(NativeFunction),
 }
@@ -109,7 +141,7 @@ mod test {
 
     #[test]
     fn call_native_function() {
-        let mut vm = Vm::new(&"");
+        let mut vm = Vm::new("").unwrap();
         assert_eq!(
             vec![] as InstructionSequence,
             NativeFunction::new(mock as NativeCode).call(&mut vm)
@@ -14,18 +14,105 @@ mod closure;
 mod compiler;
 mod exception_handler;
 mod grammar;
+mod handler_table;
 mod instructions;
 mod native;
+mod number;
+mod parse_error;
+mod source_map;
+mod trace;
+mod uncaught;
 mod value;
 mod vm;
+use exception_handler::HandlerLookup;
 use std::env;
 use std::fs::File;
 use std::io::Read;
+use std::process;
+use trace::TraceStep;
+use uncaught::{UncaughtPolicy, UNCAUGHT_LIMIT};
 use vm::Vm;
 
-fn exec(source: &String) {
-    let mut vm = Vm::new(source);
-    vm.run();
+#[derive(Clone)]
+struct Options {
+    path: String,
+    uncaught: UncaughtPolicy,
+    uncaught_limit: usize,
+    handler_lookup: HandlerLookup,
+}
+
+fn exec(source: &str, options: &Options) {
+    let policy = options.uncaught;
+    match Vm::with_file_name(&options.path, source) {
+        Ok(mut vm) => {
+            vm.set_uncaught_policy(policy);
+            vm.set_uncaught_limit(options.uncaught_limit);
+            vm.set_handler_lookup(options.handler_lookup);
+            vm.run();
+            let exceptions = vm.uncaught_exceptions();
+            let uncaught = exceptions.len() + vm.dropped_uncaught_exceptions();
+            match policy {
+                UncaughtPolicy::Strict => {
+                    if let Some(exception) = exceptions.last() {
+                        eprintln!("{}", exception);
+                        report_trace(&vm.last_trace());
+                        process::exit(1);
+                    }
+                }
+                UncaughtPolicy::Warn if uncaught > 0 => {
+                    eprintln!("uncaught exceptions: {}", uncaught)
+                }
+                _ => {}
+            }
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+}
+
+// Prints the raises that led to an uncaught one, newest first. The last step
+// of the trace is the uncaught raise itself.
+fn report_trace(trace: &[TraceStep]) {
+    for step in trace.iter().rev().skip(1) {
+        match step.location {
+            Some(ref location) => eprintln!("  after raising {} at {}", step.value, location),
+            None => eprintln!("  after raising {}", step.value),
+        }
+    }
+}
+
+// Reads `[--uncaught=ignore|warn|strict] [--uncaught-limit=<count>]
+// [--handler-lookup=callers|global] <path>` from the command line.
+fn parse_args() -> Result<Options, String> {
+    let mut path = None;
+    let mut uncaught = UncaughtPolicy::Ignore;
+    let mut uncaught_limit = UNCAUGHT_LIMIT;
+    let mut handler_lookup = HandlerLookup::Callers;
+
+    for arg in env::args().skip(1) {
+        if let Some(policy) = arg.strip_prefix("--uncaught=") {
+            uncaught = policy.parse()?;
+        } else if let Some(limit) = arg.strip_prefix("--uncaught-limit=") {
+            uncaught_limit = limit
+                .parse()
+                .map_err(|_| format!("invalid uncaught limit {:?}, expected a count", limit))?;
+        } else if let Some(lookup) = arg.strip_prefix("--handler-lookup=") {
+            handler_lookup = lookup.parse()?;
+        } else if arg.starts_with("--") {
+            return Err(format!("Unknown option {}", arg));
+        } else {
+            path = Some(arg);
+        }
+    }
+
+    path.map(|path| Options {
+        path,
+        uncaught,
+        uncaught_limit,
+        handler_lookup,
+    }).ok_or("No path given, stopping".to_string())
 }
 
 fn main() {
@@ -36,19 +123,20 @@ fn main() {
         .expect("failed to setup logging");
 
     let mut source = String::new();
-    let file_read = env::args()
-        .nth(1)
-        .ok_or("No path given, stopping".to_string())
-        .and_then(|path| File::open(path).map_err(|err| err.to_string()))
-        .and_then(|mut file| {
-            file.read_to_string(&mut source)
-                .map_err(|err| err.to_string())
-        });
+    let args = parse_args();
+    let file_read = args.clone().and_then(|options| {
+        File::open(options.path)
+            .map_err(|err| err.to_string())
+            .and_then(|mut file| {
+                file.read_to_string(&mut source)
+                    .map_err(|err| err.to_string())
+            })
+    });
     match file_read {
         Ok(_) => {
             info!("Starting VM with contents from ARGV file");
             trace!("{}", source);
-            exec(&source);
+            exec(&source, &args.unwrap());
         }
         Err(e) => error!("{}", e),
     }
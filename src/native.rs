@@ -3,15 +3,17 @@ use value::Value;
 // TODO: Make Vm a trait?
 use binding_map::BindingMap;
 use closure::Closure;
+use number::Number;
 use num::bigint::BigInt;
 use num::rational::Ratio;
 use num::ToPrimitive;
+use source_map::SourceMap;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::error::Error;
 use std::fs::File;
 use std::io::prelude::*;
-use std::net::{Shutdown, TcpListener, TcpStream};
+use std::net::{TcpListener, TcpStream};
 use std::os::unix::io::AsRawFd;
 use std::os::unix::io::FromRawFd;
 use std::os::unix::io::RawFd;
@@ -93,7 +95,7 @@ fn io_result(key: &str, value: Value) -> Value {
 }
 
 fn fd_to_number<T: AsRawFd>(fd: &T) -> Value {
-    Value::Number(Ratio::new(BigInt::from(fd.as_raw_fd()), BigInt::from(1)))
+    Value::Number(Number::from(fd.as_raw_fd() as i64))
 }
 
 fn read_file_contents(path: String) -> Result<String, String> {
@@ -188,7 +190,7 @@ fn native_socket_tcp_connect(vm: &mut Vm) -> InstructionSequence {
         }
     };
 
-    let number = Ratio::new(BigInt::from(stream.as_raw_fd()), BigInt::from(1));
+    let number = Number::from(stream.as_raw_fd() as i64);
     vm.file_descriptors
         .insert(stream.as_raw_fd(), FileDescriptor::TcpStream(stream));
 
@@ -241,8 +243,7 @@ fn native_socket_tcp_accept(vm: &mut Vm) -> InstructionSequence {
             .fetch(&"socket".to_owned())
             .expect("expected socket argument")
         {
-            Value::Number(ratio) => ratio
-                .to_integer()
+            Value::Number(number) => number
                 .to_i32()
                 .and_then(|fd| vm.file_descriptors.get(&fd))
                 .ok_or("socket not found".to_owned())
@@ -282,8 +283,7 @@ fn native_socket_tcp_accept(vm: &mut Vm) -> InstructionSequence {
 fn native_io_read_all(vm: &mut Vm) -> InstructionSequence {
     let result = {
         match vm.fetch(&"fd".to_owned()) {
-            Some(Value::Number(ratio)) => ratio
-                .to_integer()
+            Some(Value::Number(number)) => number
                 .to_i32()
                 .and_then(|fd| vm.file_descriptors.get_mut(&fd))
                 .ok_or("file descriptor not found".to_owned())
@@ -310,8 +310,7 @@ fn native_io_write(vm: &mut Vm) -> InstructionSequence {
             Some(Value::CharString(string)) => Ok(string),
             x => Err(format!("string argument is not a string: {:?}", x)),
         }.and_then(|string| match vm.fetch(&"fd".to_owned()) {
-            Some(Value::Number(ratio)) => ratio
-                .to_integer()
+            Some(Value::Number(number)) => number
                 .to_i32()
                 .and_then(|fd| vm.file_descriptors.get_mut(&fd))
                 .ok_or("file descriptor not found".to_owned())
@@ -328,15 +327,17 @@ fn native_io_write(vm: &mut Vm) -> InstructionSequence {
         }
     };
 
-    let number = Value::Number(Ratio::new(BigInt::from(bytes), BigInt::from(1)));
+    let number = Value::Number(Number::from(Ratio::from_integer(BigInt::from(bytes))));
     vm.push(io_result("io.result", number));
     vec![Instruction::Raise]
 }
 
 fn wrap_native_code(args: Vec<String>, f: NativeCode) -> Value {
-    let parent_bindings = BindingMap::new(None);
+    let parent_bindings = BindingMap::new(&Rc::new(vec![]), None);
     let closure = Closure::new(
         Rc::new(vec![Instruction::Native(NativeFunction::new(f))]),
+        Rc::new(SourceMap::new()),
+        Rc::new(args.clone()),
         &parent_bindings,
     );
     Value::Closure(Rc::new(Box::new(args)), Rc::new(closure))
@@ -421,6 +422,7 @@ pub fn find_lib(name: &str) -> Option<Value> {
 #[cfg(test)]
 mod test {
     use super::*;
+    use std::net::Shutdown;
     use test_helpers::*;
     use vm::Vm;
 
@@ -0,0 +1,249 @@
+use num::bigint::BigInt;
+use num::rational::{BigRational, Ratio};
+use num::{ToPrimitive, Zero};
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+// Numbers are rationals. Integers that fit in an `i64` are kept as such, and
+// only promoted to big rationals when a result doesn't fit or isn't whole.
+// Results that fit are demoted again, so that each number has a single
+// representation whichever way it was computed.
+#[derive(Clone, Debug)]
+pub struct Number(Repr);
+
+#[derive(Clone, Debug)]
+enum Repr {
+    Small(i64),
+    Big(BigRational),
+}
+
+impl Number {
+    pub fn to_ratio(&self) -> BigRational {
+        match self.0 {
+            Repr::Small(number) => Ratio::from_integer(BigInt::from(number)),
+            Repr::Big(ref ratio) => ratio.clone(),
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        match self.0 {
+            Repr::Small(number) => number == 0,
+            Repr::Big(ref ratio) => ratio.is_zero(),
+        }
+    }
+
+    pub fn is_integer(&self) -> bool {
+        match self.0 {
+            Repr::Small(_) => true,
+            Repr::Big(ref ratio) => ratio.is_integer(),
+        }
+    }
+
+    pub fn floor(&self) -> Number {
+        match self.0 {
+            Repr::Small(_) => self.clone(),
+            Repr::Big(ref ratio) => Number::from(ratio.floor()),
+        }
+    }
+
+    fn small(ratio: &BigRational) -> Option<i64> {
+        match ratio.is_integer() {
+            true => ratio.numer().to_i64(),
+            false => None,
+        }
+    }
+}
+
+impl From<i64> for Number {
+    fn from(number: i64) -> Number {
+        Number(Repr::Small(number))
+    }
+}
+
+impl From<BigRational> for Number {
+    fn from(ratio: BigRational) -> Number {
+        match Number::small(&ratio) {
+            Some(number) => Number(Repr::Small(number)),
+            None => Number(Repr::Big(ratio)),
+        }
+    }
+}
+
+impl From<&BigRational> for Number {
+    fn from(ratio: &BigRational) -> Number {
+        match Number::small(ratio) {
+            Some(number) => Number(Repr::Small(number)),
+            None => Number(Repr::Big(ratio.clone())),
+        }
+    }
+}
+
+macro_rules! checked_operation {
+    ($operation:ident, $method:ident, $checked:ident) => {
+        impl<'a, 'b> $operation<&'b Number> for &'a Number {
+            type Output = Number;
+
+            fn $method(self, other: &Number) -> Number {
+                if let (&Repr::Small(left), &Repr::Small(right)) = (&self.0, &other.0) {
+                    if let Some(number) = left.$checked(right) {
+                        return Number::from(number);
+                    }
+                }
+                Number::from(self.to_ratio().$method(other.to_ratio()))
+            }
+        }
+    };
+}
+
+checked_operation!(Add, add, checked_add);
+checked_operation!(Sub, sub, checked_sub);
+checked_operation!(Mul, mul, checked_mul);
+
+impl Div<&Number> for &Number {
+    type Output = Number;
+
+    fn div(self, other: &Number) -> Number {
+        if let (&Repr::Small(left), &Repr::Small(right)) = (&self.0, &other.0) {
+            if right != 0 && left.checked_rem(right) == Some(0) {
+                if let Some(number) = left.checked_div(right) {
+                    return Number::from(number);
+                }
+            }
+        }
+        Number::from(self.to_ratio() / other.to_ratio())
+    }
+}
+
+impl Neg for &Number {
+    type Output = Number;
+
+    fn neg(self) -> Number {
+        match self.0 {
+            Repr::Small(number) => match number.checked_neg() {
+                Some(number) => Number::from(number),
+                None => Number::from(-self.to_ratio()),
+            },
+            Repr::Big(ref ratio) => Number::from(-ratio),
+        }
+    }
+}
+
+impl PartialEq for Number {
+    fn eq(&self, other: &Number) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Number {}
+
+impl Ord for Number {
+    fn cmp(&self, other: &Number) -> Ordering {
+        match (&self.0, &other.0) {
+            (Repr::Small(left), Repr::Small(right)) => left.cmp(right),
+            (Repr::Big(left), Repr::Big(right)) => left.cmp(right),
+            _ => self.to_ratio().cmp(&other.to_ratio()),
+        }
+    }
+}
+
+// Each number has a single representation, so equal numbers hash the same.
+impl Hash for Number {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self.0 {
+            Repr::Small(number) => number.hash(state),
+            Repr::Big(ref ratio) => ratio.hash(state),
+        }
+    }
+}
+
+impl PartialOrd for Number {
+    fn partial_cmp(&self, other: &Number) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Whole numbers convert as is, others are truncated first.
+impl ToPrimitive for Number {
+    fn to_i64(&self) -> Option<i64> {
+        match self.0 {
+            Repr::Small(number) => Some(number),
+            Repr::Big(ref ratio) => ratio.to_integer().to_i64(),
+        }
+    }
+
+    fn to_u64(&self) -> Option<u64> {
+        match self.0 {
+            Repr::Small(number) => number.to_u64(),
+            Repr::Big(ref ratio) => ratio.to_integer().to_u64(),
+        }
+    }
+}
+
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Repr::Small(number) => write!(f, "{}", number),
+            Repr::Big(ref ratio) => write!(f, "{}", ratio),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_helpers::*;
+
+    fn big(number: i64) -> BigRational {
+        Ratio::from_integer(BigInt::from(number))
+    }
+
+    #[test]
+    fn keeps_integers_that_fit_small() {
+        match Number::from(build_ratio(4, 2)).0 {
+            Repr::Small(2) => {}
+            repr => panic!("expected a small number, got {:?}", repr),
+        }
+        match Number::from(build_ratio(1, 2)).0 {
+            Repr::Big(_) => {}
+            repr => panic!("expected a big number, got {:?}", repr),
+        }
+    }
+
+    #[test]
+    fn promotes_results_that_do_not_fit() {
+        let max = Number::from(i64::MAX);
+        let one = Number::from(1);
+        assert_eq!(big(i64::MAX) + big(1), (&max + &one).to_ratio());
+        assert_eq!(big(i64::MAX) * big(2), (&max * &Number::from(2)).to_ratio());
+        assert_eq!(
+            big(i64::MIN) - big(1),
+            (&Number::from(i64::MIN) - &one).to_ratio()
+        );
+        assert_eq!(-big(i64::MIN), (-&Number::from(i64::MIN)).to_ratio());
+        assert_eq!(
+            -big(i64::MIN),
+            (&Number::from(i64::MIN) / &Number::from(-1)).to_ratio()
+        );
+        assert_eq!(build_ratio(1, 2), (&one / &Number::from(2)).to_ratio());
+        assert_eq!(Number::from(i64::MAX), &(&max + &one) - &one);
+    }
+
+    #[test]
+    fn compares_across_representations() {
+        let half = Number::from(build_ratio(1, 2));
+        let huge = Number::from(big(i64::MAX) * big(4));
+        assert_eq!(Number::from(2), &(&half + &half) * &Number::from(2));
+        assert!(half < Number::from(1));
+        assert!(Number::from(0) < half);
+        assert!(Number::from(i64::MAX) < huge);
+        assert!(-&huge < Number::from(i64::MIN));
+    }
+
+    #[test]
+    fn renders_like_rationals() {
+        assert_eq!("-3", Number::from(-3).to_string());
+        assert_eq!("1/3", Number::from(build_ratio(1, 3)).to_string());
+    }
+}
@@ -0,0 +1,118 @@
+use grammar;
+use source_map::Location;
+use std::error::Error;
+use std::fmt;
+
+#[derive(Clone, Eq, Debug, PartialEq)]
+pub struct ParseError {
+    pub file_name: String,
+    pub line: usize,
+    pub column: usize,
+    pub source_line: String,
+    pub expected: Vec<String>,
+}
+
+impl ParseError {
+    pub fn new(file_name: &str, source: &str, error: &grammar::ParseError) -> ParseError {
+        let location = Location::new(file_name, source, error.offset);
+        let mut expected = error
+            .expected
+            .iter()
+            .map(|token| token.to_string())
+            .collect::<Vec<_>>();
+        expected.sort();
+
+        ParseError {
+            file_name: location.file_name,
+            line: location.line,
+            column: location.column,
+            source_line: location.source_line,
+            expected,
+        }
+    }
+
+    fn caret_padding(&self) -> String {
+        self.source_line
+            .chars()
+            .take(self.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect()
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let gutter = " ".repeat(self.line.to_string().len());
+
+        writeln!(
+            f,
+            "syntax error at {}:{}:{}",
+            self.file_name, self.line, self.column
+        )?;
+        writeln!(f, "{} |", gutter)?;
+        writeln!(f, "{} | {}", self.line, self.source_line)?;
+        writeln!(f, "{} | {}^", gutter, self.caret_padding())?;
+        let expected = self
+            .expected
+            .iter()
+            .map(|token| format!("`{}`", token.escape_default()))
+            .collect::<Vec<_>>();
+        match expected.len() {
+            0 => write!(f, "expected end of input"),
+            1 => write!(f, "expected {}", expected[0]),
+            _ => write!(f, "expected one of {}", expected.join(", ")),
+        }
+    }
+}
+
+impl Error for ParseError {
+    fn description(&self) -> &str {
+        "syntax error"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use grammar::statements;
+
+    fn parse_error(source: &str) -> ParseError {
+        ParseError::new("test.!", source, &statements(source).unwrap_err())
+    }
+
+    #[test]
+    fn reports_line_column_and_source_line() {
+        let error = parse_error("let a = 1\nlet b = = 2\nlet c = 3");
+
+        assert_eq!("test.!", error.file_name);
+        assert_eq!(2, error.line);
+        assert_eq!(9, error.column);
+        assert_eq!("let b = = 2", error.source_line);
+        assert!(error.expected.contains(&"fn".to_owned()));
+    }
+
+    #[test]
+    fn sorts_expected_tokens() {
+        let error = parse_error("let a = ");
+        let mut sorted = error.expected.clone();
+        sorted.sort();
+
+        assert_eq!(sorted, error.expected);
+    }
+
+    #[test]
+    fn renders_a_caret_under_the_offending_column() {
+        let error = ParseError {
+            file_name: "test.!".to_owned(),
+            line: 12,
+            column: 5,
+            source_line: "let = 1".to_owned(),
+            expected: vec!["[a-zA-Z]".to_owned()],
+        };
+
+        assert_eq!(
+            "syntax error at test.!:12:5\n   |\n12 | let = 1\n   |     ^\nexpected `[a-zA-Z]`",
+            error.to_string()
+        );
+    }
+}
@@ -0,0 +1,146 @@
+use ast::Span;
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+// Spans of the nodes each instruction of a sequence was compiled from, keyed
+// by instruction index. Like spans, source maps don't take part in
+// comparisons of the instructions and closures that carry them.
+#[derive(Clone, Debug, Default)]
+pub struct SourceMap {
+    spans: Vec<Span>,
+}
+
+impl SourceMap {
+    pub fn new() -> SourceMap {
+        SourceMap { spans: Vec::new() }
+    }
+
+    pub fn push(&mut self, span: Span) {
+        self.spans.push(span);
+    }
+
+    pub fn span_at(&self, index: usize) -> Option<Span> {
+        self.spans.get(index).cloned()
+    }
+}
+
+impl PartialEq for SourceMap {
+    fn eq(&self, _: &SourceMap) -> bool {
+        true
+    }
+}
+
+impl Eq for SourceMap {}
+
+impl Ord for SourceMap {
+    fn cmp(&self, _: &SourceMap) -> Ordering {
+        Ordering::Equal
+    }
+}
+
+impl PartialOrd for SourceMap {
+    fn partial_cmp(&self, other: &SourceMap) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Hash for SourceMap {
+    fn hash<H: Hasher>(&self, _: &mut H) {}
+}
+
+#[derive(Clone, Eq, Debug, PartialEq)]
+pub struct Source {
+    pub file_name: String,
+    pub text: String,
+}
+
+impl Source {
+    pub fn new(file_name: &str, text: &str) -> Source {
+        Source {
+            file_name: file_name.to_owned(),
+            text: text.to_owned(),
+        }
+    }
+
+    pub fn location(&self, span: Span) -> Location {
+        Location::new(&self.file_name, &self.text, span.start)
+    }
+}
+
+#[derive(Clone, Eq, Debug, PartialEq)]
+pub struct Location {
+    pub file_name: String,
+    pub line: usize,
+    pub column: usize,
+    pub source_line: String,
+}
+
+impl Location {
+    pub fn new(file_name: &str, text: &str, offset: usize) -> Location {
+        let offset = offset.min(text.len());
+        let line_start = text[..offset].rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let line_end = text[offset..]
+            .find('\n')
+            .map(|i| offset + i)
+            .unwrap_or(text.len());
+
+        Location {
+            file_name: file_name.to_owned(),
+            line: text[..line_start].matches('\n').count() + 1,
+            column: text[line_start..offset].chars().count() + 1,
+            source_line: text[line_start..line_end].to_owned(),
+        }
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file_name, self.line, self.column)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn looks_up_spans_by_instruction_index() {
+        let mut source_map = SourceMap::new();
+        source_map.push(Span::new(0, 3));
+        source_map.push(Span::new(4, 9));
+
+        assert_eq!(Some(4), source_map.span_at(1).map(|span| span.start));
+        assert_eq!(None, source_map.span_at(2).map(|span| span.start));
+    }
+
+    #[test]
+    fn locates_offsets() {
+        let text = "let a = 1\nlet bé = 2\n";
+
+        assert_eq!(
+            Location {
+                file_name: "test.!".to_owned(),
+                line: 1,
+                column: 1,
+                source_line: "let a = 1".to_owned(),
+            },
+            Location::new("test.!", text, 0)
+        );
+        assert_eq!(
+            Location {
+                file_name: "test.!".to_owned(),
+                line: 2,
+                column: 9,
+                source_line: "let bé = 2".to_owned(),
+            },
+            Location::new("test.!", text, 19)
+        );
+        assert_eq!(
+            "test.!:2:9",
+            Source::new("test.!", text)
+                .location(Span::new(19, 20))
+                .to_string()
+        );
+    }
+}
@@ -1,12 +1,14 @@
 #[cfg(test)]
 use ast::*;
-use binding_map::BindingMap;
+use binding_map::{Binding, BindingMap};
 use closure::Closure;
 use grammar::*;
 use instructions::*;
+use number::Number;
 use num::rational::Ratio;
 use num::BigInt;
 use regex::Regex;
+use source_map::SourceMap;
 use std::cell::RefCell;
 use std::collections::BTreeMap;
 use std::rc::*;
@@ -17,7 +19,7 @@ pub fn l_string(string: &str) -> Literal {
 }
 
 pub fn l_number(num: i64, denom: i64) -> Literal {
-    Literal::Number(build_ratio(num, denom))
+    Literal::Number(Number::from(build_ratio(num, denom)))
 }
 
 pub fn l_bool(b: bool) -> Literal {
@@ -35,73 +37,158 @@ pub fn l_map(pairs: Vec<(Expression, Expression)>) -> Literal {
     Literal::Map(pairs)
 }
 
+pub fn l_list(items: Vec<Expression>) -> Literal {
+    Literal::List(items)
+}
+
+pub fn p_list(items: Vec<Pattern>, tail: Option<&str>) -> Pattern {
+    Pattern::List(items, tail.map(|name| name.to_owned()), Span::default())
+}
+
 pub fn p_map(pairs: Vec<(Pattern, Pattern)>) -> Pattern {
-    Pattern::Map(pairs)
+    Pattern::Map(pairs, false, Span::default())
+}
+
+pub fn p_exact_map(pairs: Vec<(Pattern, Pattern)>) -> Pattern {
+    Pattern::Map(pairs, true, Span::default())
+}
+
+pub fn p_wildcard() -> Pattern {
+    Pattern::Wildcard(Span::default())
+}
+
+pub fn p_alternatives(alternatives: Vec<Pattern>) -> Pattern {
+    Pattern::Alternatives(alternatives, Span::default())
+}
+
+pub fn p_type(value_type: ValueType, pattern: Pattern) -> Pattern {
+    Pattern::Type(value_type, Box::new(pattern), Span::default())
+}
+
+pub fn p_range(from: i64, to: i64) -> Pattern {
+    Pattern::Range(
+        Number::from(build_ratio(from, 1)),
+        Number::from(build_ratio(to, 1)),
+        Span::default(),
+    )
+}
+
+pub fn p_pin(name: &str, slots: Vec<(usize, usize)>) -> Pattern {
+    Pattern::Pin(Rc::new(Binding::new(name, slots)), Span::default())
+}
+
+pub fn p_as(pattern: Pattern, name: &str) -> Pattern {
+    Pattern::As(Box::new(pattern), name.to_owned(), Span::default())
 }
 
 pub fn p_bool(b: bool) -> Pattern {
-    Pattern::Boolean(b)
+    Pattern::Boolean(b, Span::default())
 }
 
 pub fn p_number(num: i64, denom: i64) -> Pattern {
-    Pattern::Number(build_ratio(num, denom))
+    Pattern::Number(Number::from(build_ratio(num, denom)), Span::default())
 }
 
 pub fn p_string(string: &str) -> Pattern {
-    Pattern::CharString(string.to_owned())
+    Pattern::CharString(string.to_owned(), Span::default())
 }
 
 pub fn p_ident(name: &str) -> Pattern {
-    Pattern::Identifier(name.to_owned())
+    Pattern::Identifier(name.to_owned(), Span::default())
 }
 
 pub fn p_string_match(bindings: Vec<&str>, regex: &str) -> Pattern {
+    p_typed_string_match(
+        bindings
+            .into_iter()
+            .map(|b| Some((b, ValueType::CharString)))
+            .collect(),
+        regex,
+    )
+}
+
+pub fn p_typed_string_match(bindings: Vec<Option<(&str, ValueType)>>, regex: &str) -> Pattern {
     Pattern::StringMatch(
-        bindings.into_iter().map(|b| b.to_owned()).collect(),
+        bindings
+            .into_iter()
+            .map(|b| b.map(|(name, value_type)| (name.to_owned(), value_type)))
+            .collect(),
         StringMatcher {
             regex: Regex::new(&(r#"(?s)\A"#.to_string() + regex + r#"\z"#)).unwrap(),
         },
+        Span::default(),
     )
 }
 
 pub fn s_assign(name: &str, literal: Literal) -> Statement {
-    Statement::Assign(true, name.to_owned(), Box::new(e_literal(literal)))
+    Statement::Assign(
+        true,
+        name.to_owned(),
+        Box::new(e_literal(literal)),
+        Span::default(),
+    )
 }
 
 pub fn s_index_assign(target: Expression, property: Expression, value: Expression) -> Statement {
-    Statement::IndexAssign(Box::new(target), Box::new(property), Box::new(value))
+    Statement::IndexAssign(
+        Box::new(target),
+        Box::new(property),
+        Box::new(value),
+        Span::default(),
+    )
 }
 
 pub fn s_call(target: Expression, args: Vec<Expression>) -> Statement {
-    Statement::Call(Box::new(target), args)
+    Statement::Call(Box::new(target), args, Span::default())
 }
 
 pub fn s_raise(exp: Expression) -> Statement {
-    Statement::Raise(exp)
+    Statement::Raise(exp, Span::default())
+}
+
+pub fn s_reraise(exp: Option<Expression>) -> Statement {
+    Statement::Reraise(exp, Span::default())
+}
+
+pub fn s_propagate(exp: Option<Expression>) -> Statement {
+    Statement::Propagate(exp, Span::default())
 }
 
 pub fn s_rescue(map: Pattern, statements: Vec<Statement>) -> Statement {
-    Statement::Rescue(map, Box::new(statements))
+    Statement::Rescue(map, None, Box::new(statements), Span::default())
+}
+
+pub fn s_rescue_when(map: Pattern, guard: Expression, statements: Vec<Statement>) -> Statement {
+    Statement::Rescue(
+        map,
+        Some(Box::new(guard)),
+        Box::new(statements),
+        Span::default(),
+    )
 }
 
 pub fn e_literal(literal: Literal) -> Expression {
-    Expression::Literal(literal)
+    Expression::Literal(literal, Span::default())
 }
 
 pub fn e_identifier(name: &str) -> Expression {
-    Expression::Identifier(name.to_owned())
+    Expression::Identifier(name.to_owned(), Span::default())
 }
 
 pub fn e_binop(op: &str, left: Expression, right: Expression) -> Expression {
-    Expression::BinOp(op.to_owned(), Box::new(left), Box::new(right))
+    Expression::BinOp(op.to_owned(), Box::new(left), Box::new(right), Span::default())
+}
+
+pub fn e_unaryop(op: &str, operand: Expression) -> Expression {
+    Expression::UnaryOp(op.to_owned(), Box::new(operand), Span::default())
 }
 
 pub fn e_index_access(target: Expression, property: Expression) -> Expression {
-    Expression::IndexAccess(Box::new(target), Box::new(property))
+    Expression::IndexAccess(Box::new(target), Box::new(property), Span::default())
 }
 
 pub fn e_import(name: Expression) -> Expression {
-    Expression::Import(Box::new(name))
+    Expression::Import(Box::new(name), Span::default())
 }
 
 pub fn build_ratio(num: i64, denom: i64) -> Ratio<BigInt> {
@@ -129,7 +216,7 @@ pub fn v_string(str: &str) -> Value {
 }
 
 pub fn v_number(num: i64, denom: i64) -> Value {
-    Value::Number(build_ratio(num, denom))
+    Value::Number(Number::from(build_ratio(num, denom)))
 }
 
 pub fn v_map(pairs: Vec<(Value, Value)>) -> Value {
@@ -140,13 +227,22 @@ pub fn v_map(pairs: Vec<(Value, Value)>) -> Value {
     Value::Map(Rc::new(RefCell::new(map)))
 }
 
+pub fn v_list(items: Vec<Value>) -> Value {
+    Value::List(Rc::new(RefCell::new(items)))
+}
+
 pub fn v_closure(
     args: Vec<String>,
     insns: InstructionSequence,
     parent_bindings: Option<&BindingMap>,
 ) -> Value {
-    let bindings = BindingMap::new(parent_bindings);
-    let closure = Closure::new(Rc::new(insns), &bindings);
+    let bindings = BindingMap::new(&Rc::new(vec![]), parent_bindings);
+    let closure = Closure::new(
+        Rc::new(insns),
+        Rc::new(SourceMap::new()),
+        Rc::new(args.clone()),
+        &bindings,
+    );
     Value::Closure(Rc::new(Box::new(args)), Rc::new(closure))
 }
 
@@ -0,0 +1,191 @@
+use ast::Span;
+use source_map::{Location, Source};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+use value::Value;
+
+// How many raises a trace remembers, older ones are dropped first.
+pub const TRACE_LIMIT: usize = 16;
+
+// A raise as the VM records it. Spans are only resolved to locations when
+// the trace is looked at, as most traces never are.
+#[derive(Clone, Eq, Debug, PartialEq)]
+pub struct TraceEntry {
+    pub value: Value,
+    pub location: Option<Span>,
+    pub closure: Option<Span>,
+    pub handler: Option<Span>,
+}
+
+impl TraceEntry {
+    pub fn new(value: Value, location: Option<Span>, closure: Option<Span>) -> TraceEntry {
+        TraceEntry {
+            value,
+            location,
+            closure,
+            handler: None,
+        }
+    }
+}
+
+// The raises that led to a frame, oldest first. Traces share the raises they
+// were extended from, so that raising doesn't copy them.
+#[derive(Clone, Debug, Default)]
+pub struct Trace {
+    last: Option<Rc<TraceNode>>,
+}
+
+#[derive(Debug)]
+struct TraceNode {
+    entry: TraceEntry,
+    previous: Trace,
+    // How many nodes the trace has, including this one.
+    length: usize,
+}
+
+impl Trace {
+    pub fn new() -> Trace {
+        Trace { last: None }
+    }
+
+    fn length(&self) -> usize {
+        self.last.as_ref().map_or(0, |node| node.length)
+    }
+
+    // The trace with an entry added to its end. Only the last `TRACE_LIMIT`
+    // entries are kept, but nodes are only dropped once there are twice as
+    // many, so that each raise copies one entry on average.
+    pub fn extend(&self, entry: TraceEntry) -> Trace {
+        let previous = match self.length() < 2 * TRACE_LIMIT {
+            true => self.clone(),
+            false => self
+                .entries()
+                .into_iter()
+                .skip(1)
+                .fold(Trace::new(), |trace, entry| trace.push(entry.clone())),
+        };
+        previous.push(entry)
+    }
+
+    fn push(&self, entry: TraceEntry) -> Trace {
+        Trace {
+            last: Some(Rc::new(TraceNode {
+                entry,
+                previous: self.clone(),
+                length: self.length() + 1,
+            })),
+        }
+    }
+
+    // The last `TRACE_LIMIT` entries, oldest first.
+    pub fn entries(&self) -> Vec<&TraceEntry> {
+        let mut entries = Vec::new();
+        let mut node = self.last.as_ref();
+        while let Some(current) = node {
+            if entries.len() == TRACE_LIMIT {
+                break;
+            }
+            entries.push(&current.entry);
+            node = current.previous.last.as_ref();
+        }
+        entries.reverse();
+        entries
+    }
+}
+
+impl PartialEq for Trace {
+    fn eq(&self, other: &Trace) -> bool {
+        self.entries() == other.entries()
+    }
+}
+
+impl Eq for Trace {}
+
+// One raise of a trace: the raised value, where it was raised, where the
+// closure that raised it was defined and which rescue block caught it.
+#[derive(Clone, Eq, Debug, PartialEq)]
+pub struct TraceStep {
+    pub value: Value,
+    pub location: Option<Location>,
+    pub closure: Option<Location>,
+    pub handler: Option<Location>,
+}
+
+impl TraceStep {
+    pub fn new(entry: &TraceEntry, source: &Source) -> TraceStep {
+        TraceStep {
+            value: entry.value.clone(),
+            location: entry.location.map(|span| source.location(span)),
+            closure: entry.closure.map(|span| source.location(span)),
+            handler: entry.handler.map(|span| source.location(span)),
+        }
+    }
+
+    // The step as a map, leaving out the locations that aren't known.
+    pub fn to_value(&self) -> Value {
+        let mut map = BTreeMap::new();
+        map.insert(Value::CharString("value".to_owned()), self.value.clone());
+        let locations = vec![
+            ("location", &self.location),
+            ("closure", &self.closure),
+            ("handler", &self.handler),
+        ];
+        for (key, location) in locations.into_iter() {
+            if let Some(location) = location {
+                map.insert(
+                    Value::CharString(key.to_owned()),
+                    Value::CharString(location.to_string()),
+                );
+            }
+        }
+        Value::Map(Rc::new(RefCell::new(map)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_helpers::*;
+
+    fn entry(number: i64) -> TraceEntry {
+        TraceEntry::new(v_number(number, 1), None, None)
+    }
+
+    #[test]
+    fn extends_traces_up_to_the_limit() {
+        let trace = Trace::new().extend(entry(1));
+        assert_eq!(vec![&entry(1), &entry(2)], trace.extend(entry(2)).entries());
+        assert_eq!(vec![&entry(1)], trace.entries());
+
+        let trace = (0..3 * TRACE_LIMIT as i64).fold(Trace::new(), |trace, number| {
+            trace.extend(entry(number))
+        });
+        let entries = trace.entries();
+        assert_eq!(TRACE_LIMIT, entries.len());
+        assert_eq!(&entry(2 * TRACE_LIMIT as i64), entries[0]);
+        assert_eq!(&entry(3 * TRACE_LIMIT as i64 - 1), entries[TRACE_LIMIT - 1]);
+        assert!(trace.length() <= 2 * TRACE_LIMIT);
+    }
+
+    #[test]
+    fn converts_steps_to_values() {
+        let source = Source::new("test.!", "raise(1)");
+        let mut entry = entry(1);
+        entry.location = Some(Span::new(0, 8));
+        let step = TraceStep::new(&entry, &source);
+
+        assert_eq!(
+            Some("test.!:1:1".to_owned()),
+            step.location.map(|l| l.to_string())
+        );
+        assert_eq!(None, step.handler);
+        assert_eq!(
+            v_map(vec![
+                (v_string("value"), v_number(1, 1)),
+                (v_string("location"), v_string("test.!:1:1")),
+            ]),
+            TraceStep::new(&entry, &source).to_value()
+        );
+    }
+}
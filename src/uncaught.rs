@@ -0,0 +1,115 @@
+use ast::Span;
+use source_map::Location;
+use std::fmt;
+use std::str::FromStr;
+use value::Value;
+
+// How many uncaught exceptions the VM keeps, older ones are dropped first.
+pub const UNCAUGHT_LIMIT: usize = 1024;
+
+// What the VM does with a raised value that no handler matches.
+#[derive(Clone, Copy, Eq, Debug, PartialEq)]
+pub enum UncaughtPolicy {
+    // Carry on silently, which is how the language has always behaved.
+    Ignore,
+    // Report the value and where it was raised on stderr, then carry on.
+    Warn,
+    // Stop running the program.
+    Strict,
+}
+
+impl FromStr for UncaughtPolicy {
+    type Err = String;
+
+    fn from_str(policy: &str) -> Result<UncaughtPolicy, String> {
+        match policy {
+            "ignore" => Ok(UncaughtPolicy::Ignore),
+            "warn" => Ok(UncaughtPolicy::Warn),
+            "strict" => Ok(UncaughtPolicy::Strict),
+            _ => Err(format!(
+                "unknown policy for uncaught exceptions {:?}, expected ignore, warn or strict",
+                policy
+            )),
+        }
+    }
+}
+
+// An uncaught raise as the VM records it. The span is only resolved to a
+// location when the raise is reported.
+#[derive(Clone, Eq, Debug, PartialEq)]
+pub struct UncaughtEntry {
+    pub value: Value,
+    pub location: Option<Span>,
+}
+
+impl UncaughtEntry {
+    pub fn new(value: Value, location: Option<Span>) -> UncaughtEntry {
+        UncaughtEntry {
+            value,
+            location,
+        }
+    }
+}
+
+#[derive(Clone, Eq, Debug, PartialEq)]
+pub struct UncaughtException {
+    pub value: Value,
+    pub location: Option<Location>,
+}
+
+impl UncaughtException {
+    pub fn new(value: Value, location: Option<Location>) -> UncaughtException {
+        UncaughtException {
+            value,
+            location,
+        }
+    }
+}
+
+impl fmt::Display for UncaughtException {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.location {
+            Some(ref location) => {
+                let gutter = " ".repeat(location.line.to_string().len());
+
+                writeln!(f, "uncaught exception at {}", location)?;
+                writeln!(f, "{} |", gutter)?;
+                writeln!(f, "{} | {}", location.line, location.source_line)?;
+                writeln!(f, "{} |", gutter)?;
+                write!(f, "{} = {}", gutter, self.value)
+            }
+            None => write!(f, "uncaught exception {}", self.value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_helpers::*;
+
+    #[test]
+    fn parses_policies() {
+        assert_eq!(Ok(UncaughtPolicy::Ignore), "ignore".parse());
+        assert_eq!(Ok(UncaughtPolicy::Warn), "warn".parse());
+        assert_eq!(Ok(UncaughtPolicy::Strict), "strict".parse());
+        assert_err!("loud".parse::<UncaughtPolicy>());
+    }
+
+    #[test]
+    fn renders_the_value_and_where_it_was_raised() {
+        let exception = UncaughtException::new(
+            v_map(vec![(v_string("error"), v_string("key_error"))]),
+            Some(Location::new("test.!", "let a = 1\nraise(a)", 10)),
+        );
+
+        assert_eq!(
+            "uncaught exception at test.!:2:1\n  |\n2 | raise(a)\n  |\n  = { \"error\" => \"key_error\" }",
+            exception.to_string()
+        );
+        assert_eq!(
+            "uncaught exception 1",
+            UncaughtException::new(v_number(1, 1), None).to_string()
+        );
+    }
+}
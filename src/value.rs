@@ -1,18 +1,26 @@
 use closure::Closure;
+use number::Number;
 
 use num::bigint::{BigInt, ToBigInt};
 use num::rational::{BigRational, Ratio};
-use num::{range, One, ToPrimitive, Zero};
+use num::{pow, range, Signed, ToPrimitive, Zero};
 use std::cell::RefCell;
+use std::cmp;
 use std::collections::BTreeMap;
+use std::fmt;
 use std::rc::Rc;
 
+// How many bits the numerator or denominator of a power can take, so that
+// huge exponents raise an error instead of running out of memory.
+pub const POW_LIMIT_BITS: usize = 1 << 16;
+
 #[derive(Clone, Eq, Debug, PartialEq, PartialOrd, Ord)]
 pub enum Value {
-    Number(BigRational),
+    Number(Number),
     CharString(String),
     Boolean(bool),
     Map(Rc<RefCell<BTreeMap<Value, Value>>>),
+    List(Rc<RefCell<Vec<Value>>>),
     Closure(Rc<Box<Vec<String>>>, Rc<Closure>),
 }
 
@@ -21,10 +29,10 @@ type BinopResult = Result<Value, String>;
 impl Value {
     pub fn sub(&self, other: Value) -> BinopResult {
         match (self, other) {
-            (&Value::Number(ref lratio), Value::Number(ref rratio)) => {
-                Ok(Value::Number(lratio - rratio))
+            (Value::Number(lnumber), Value::Number(ref rnumber)) => {
+                Ok(Value::Number(lnumber - rnumber))
             }
-            (&Value::CharString(ref lstr), Value::CharString(ref rstr)) => {
+            (Value::CharString(lstr), Value::CharString(ref rstr)) => {
                 if let Some(index) = lstr.rfind(rstr) {
                     Ok(Value::CharString(
                         lstr[0..index].to_owned() + &lstr[(index + rstr.len())..],
@@ -33,13 +41,13 @@ impl Value {
                     Ok(Value::CharString("".to_owned()))
                 }
             }
-            (&Value::Closure(_, _), Value::Closure(_, _)) => {
+            (Value::Closure(_, _), Value::Closure(_, _)) => {
                 Err(format!("Subtraction of closures is not supported"))
             }
-            (&Value::Boolean(ref lbool), Value::Boolean(ref rbool)) => {
+            (Value::Boolean(lbool), Value::Boolean(ref rbool)) => {
                 Ok(Value::Boolean(lbool ^ rbool))
             }
-            (&Value::Map(ref lmap), Value::Map(ref rmap)) => {
+            (Value::Map(lmap), Value::Map(ref rmap)) => {
                 let result = lmap
                     .borrow()
                     .clone()
@@ -60,11 +68,18 @@ impl Value {
 
     pub fn mul(&self, right: Value) -> BinopResult {
         match (self, right) {
-            (&Value::Number(ref lratio), Value::Number(ref rratio)) => {
-                Ok(Value::Number(lratio * rratio))
+            (Value::Number(lnumber), Value::Number(ref rnumber)) => {
+                Ok(Value::Number(lnumber * rnumber))
             }
-            (&Value::CharString(ref str), Value::Number(ref ratio))
-            | (&Value::Number(ref ratio), Value::CharString(ref str)) => {
+            (&Value::CharString(ref str), Value::Number(ref number))
+            | (&Value::Number(ref number), Value::CharString(ref str)) => {
+                if *number < Number::from(0) {
+                    return Err(format!(
+                        "Strings can't be repeated a negative number of times, got {}",
+                        number
+                    ));
+                }
+                let ratio = number.to_ratio();
                 let extended: String = range(BigInt::from(0), ratio.ceil().to_integer())
                     .map(|_| str.clone())
                     .collect::<Vec<String>>()
@@ -77,54 +92,46 @@ impl Value {
                     extended[..truncation_index.to_usize().unwrap()].to_owned(),
                 ))
             }
-            (&Value::Map(ref map), Value::Number(ref ratio))
-            | (&Value::Number(ref ratio), Value::Map(ref map)) => {
+            (&Value::Map(ref map), Value::Number(ref number))
+            | (&Value::Number(ref number), Value::Map(ref map)) => {
                 let result = map
                     .borrow()
-                    .clone()
-                    .into_iter()
-                    .map(|(ref key, ref value)| {
-                        (
-                            key.mul(Value::Number(ratio.clone()))
-                                .expect("everything is multipliable by a number"),
-                            value
-                                .mul(Value::Number(ratio.clone()))
-                                .expect("everything is multipliable by a number"),
-                        )
+                    .iter()
+                    .map(|(key, value)| {
+                        Ok((
+                            key.mul(Value::Number(number.clone()))?,
+                            value.mul(Value::Number(number.clone()))?,
+                        ))
                     })
-                    .collect();
+                    .collect::<Result<_, String>>()?;
 
                 Ok(Value::Map(Rc::new(RefCell::new(result))))
             }
-            (&Value::Boolean(ref boolean), Value::Number(ref ratio))
-            | (&Value::Number(ref ratio), Value::Boolean(ref boolean)) => match boolean {
-                &true => Ok(Value::Number(ratio.clone())),
-                &false => Ok(Value::Number(Ratio::new(BigInt::zero(), BigInt::one()))),
+            (&Value::Boolean(ref boolean), Value::Number(ref number))
+            | (&Value::Number(ref number), Value::Boolean(ref boolean)) => match *boolean {
+                true => Ok(Value::Number(number.clone())),
+                false => Ok(Value::Number(Number::from(0))),
             },
             (&Value::CharString(ref str), Value::Boolean(ref boolean))
             | (&Value::Boolean(ref boolean), Value::CharString(ref str)) => match boolean {
                 &true => Ok(Value::CharString(str.clone())),
                 &false => Ok(Value::CharString("".to_owned())),
             },
-            (&Value::Boolean(ref lbool), Value::Boolean(ref rbool)) => {
+            (Value::Boolean(lbool), Value::Boolean(ref rbool)) => {
                 Ok(Value::Boolean(lbool & rbool))
             }
             (&Value::Boolean(ref boolean), Value::Map(ref map))
             | (&Value::Map(ref map), Value::Boolean(ref boolean)) => {
                 let result = map
                     .borrow()
-                    .clone()
-                    .into_iter()
-                    .map(|(ref key, ref value)| {
-                        (
-                            key.mul(Value::Boolean(*boolean))
-                                .expect("everything is multipliable by a boolean"),
-                            value
-                                .mul(Value::Boolean(*boolean))
-                                .expect("everything is multipliable by a boolean"),
-                        )
+                    .iter()
+                    .map(|(key, value)| {
+                        Ok((
+                            key.mul(Value::Boolean(*boolean))?,
+                            value.mul(Value::Boolean(*boolean))?,
+                        ))
                     })
-                    .collect();
+                    .collect::<Result<_, String>>()?;
 
                 Ok(Value::Map(Rc::new(RefCell::new(result))))
             }
@@ -134,18 +141,23 @@ impl Value {
 
     pub fn div(&self, right: Value) -> BinopResult {
         match (self, right) {
-            (&Value::Number(ref lratio), Value::Number(ref rratio)) => {
-                if rratio.is_zero() {
+            (Value::Number(lnumber), Value::Number(ref rnumber)) => {
+                if rnumber.is_zero() {
                     Err("Can't divide by zero".to_owned())
                 } else {
-                    Ok(Value::Number(lratio / rratio))
+                    Ok(Value::Number(lnumber / rnumber))
                 }
             }
-            (&Value::CharString(_), Value::Number(ref ratio)) => {
-                if ratio.is_zero() {
+            (&Value::CharString(_), Value::Number(ref number)) => {
+                if number.is_zero() {
                     Err("Can't divide by zero".to_owned())
+                } else if *number < Number::from(0) {
+                    Err(format!(
+                        "Strings can't be divided by a negative number, got {}",
+                        number
+                    ))
                 } else {
-                    self.mul(Value::Number(Ratio::from_integer(BigInt::from(1)) / ratio))
+                    self.mul(Value::Number(&Number::from(1) / number))
                 }
             }
 
@@ -153,35 +165,174 @@ impl Value {
         }
     }
 
+    pub fn floor_div(&self, right: Value) -> BinopResult {
+        match (self, right) {
+            (Value::Number(lnumber), Value::Number(ref rnumber)) => {
+                if rnumber.is_zero() {
+                    Err("Can't divide by zero".to_owned())
+                } else {
+                    Ok(Value::Number((lnumber / rnumber).floor()))
+                }
+            }
+            (Value::CharString(str), Value::Number(ref number)) => {
+                Value::string_parts(str, &number.to_ratio()).map(|(length, _)| {
+                    Value::CharString(str.chars().take(length).collect())
+                })
+            }
+            (l, r) => Err(format!("Unsupported operation // for {:?} and {:?}", l, r)),
+        }
+    }
+
+    pub fn rem(&self, right: Value) -> BinopResult {
+        match (self, right) {
+            (Value::Number(lnumber), Value::Number(ref rnumber)) => {
+                if rnumber.is_zero() {
+                    Err("Can't divide by zero".to_owned())
+                } else {
+                    Ok(Value::Number(
+                        lnumber - &(rnumber * &(lnumber / rnumber).floor()),
+                    ))
+                }
+            }
+            (Value::CharString(str), Value::Number(ref number)) => {
+                Value::string_parts(str, &number.to_ratio()).map(|(_, leftover)| {
+                    let skip = str.chars().count() - leftover;
+                    Value::CharString(str.chars().skip(skip).collect())
+                })
+            }
+            (l, r) => Err(format!("Unsupported operation % for {:?} and {:?}", l, r)),
+        }
+    }
+
+    pub fn pow(&self, right: Value) -> BinopResult {
+        match (self, right) {
+            (Value::Number(base), Value::Number(ref exponent)) => {
+                let (base, exponent) = (base.to_ratio(), exponent.to_ratio());
+                if !exponent.is_integer() {
+                    return Err(format!("Exponent must be an integer, got {}", exponent));
+                }
+                if base.is_zero() && exponent < Ratio::zero() {
+                    return Err("Can't divide by zero".to_owned());
+                }
+                let power = match exponent.to_integer().abs().to_usize() {
+                    Some(power) => power,
+                    None => return Err(format!("Exponent {} is too large", exponent)),
+                };
+                // Powers of 0, 1 and -1 don't grow, whatever the exponent.
+                let bits = cmp::max(base.numer().bits(), base.denom().bits());
+                if power.saturating_mul(bits.saturating_sub(1)) > POW_LIMIT_BITS {
+                    return Err(format!("Exponent {} is too large", exponent));
+                }
+                let numer = pow(base.numer().clone(), power);
+                let denom = pow(base.denom().clone(), power);
+                if exponent < Ratio::zero() {
+                    Ok(Value::Number(Number::from(Ratio::new(denom, numer))))
+                } else {
+                    Ok(Value::Number(Number::from(Ratio::new(numer, denom))))
+                }
+            }
+            (l, r) => Err(format!("Unsupported operation ** for {:?} and {:?}", l, r)),
+        }
+    }
+
+    // Splits a string in `parts` equal parts, returning the length of each
+    // part and the number of characters left over.
+    fn string_parts(str: &str, parts: &BigRational) -> Result<(usize, usize), String> {
+        if parts.is_zero() {
+            return Err("Can't divide by zero".to_owned());
+        }
+        match parts.to_integer().to_usize() {
+            Some(count) if parts.is_integer() => {
+                let length = str.chars().count();
+                Ok((length / count, length % count))
+            }
+            _ => Err(format!(
+                "Strings can only be split in a positive whole number of parts, got {}",
+                parts
+            )),
+        }
+    }
+
     pub fn add(&self, right: Value) -> BinopResult {
         match (self, right) {
-            (&Value::Number(ref lratio), Value::Number(ref rratio)) => {
-                Ok(Value::Number(lratio + rratio))
+            (Value::Number(lnumber), Value::Number(ref rnumber)) => {
+                Ok(Value::Number(lnumber + rnumber))
             }
-            (&Value::CharString(ref lstr), Value::CharString(ref rstr)) => {
+            (Value::CharString(lstr), Value::CharString(ref rstr)) => {
                 Ok(Value::CharString((*lstr).clone() + rstr))
             }
-            (&Value::CharString(ref lstr), Value::Number(ref rratio)) => {
-                Ok(Value::CharString((*lstr).clone() + &format!("{}", rratio)))
+            (Value::CharString(lstr), Value::Number(ref rnumber)) => {
+                Ok(Value::CharString((*lstr).clone() + &format!("{}", rnumber)))
             }
-            (&Value::Closure(_, _), Value::Closure(_, _)) => {
+            (Value::Closure(_, _), Value::Closure(_, _)) => {
                 Err("Addition of closures is not supported".to_owned())
             }
             (&Value::Boolean(lbool), Value::Boolean(rbool)) => Ok(Value::Boolean(lbool || rbool)),
-            (&Value::Map(ref lmap), Value::Map(ref rmap)) => {
+            (Value::Map(lmap), Value::Map(ref rmap)) => {
                 let mut result = (*lmap.borrow()).clone();
                 let mut rclone = (*rmap.borrow()).clone();
                 result.append(&mut rclone);
                 Ok(Value::Map(Rc::new(RefCell::new(result))))
             }
+            (Value::List(llist), Value::List(ref rlist)) => {
+                let mut result = (*llist.borrow()).clone();
+                result.extend(rlist.borrow().iter().cloned());
+                Ok(Value::List(Rc::new(RefCell::new(result))))
+            }
             (l, r) => Err(format!("Unsupported operation + for {:?} and {:?}", l, r)),
         }
     }
 
+    pub fn neg(&self) -> BinopResult {
+        match self {
+            Value::Number(number) => Ok(Value::Number(-number)),
+            v => Err(format!("Unsupported operation - for {:?}", v)),
+        }
+    }
+
+    pub fn not(&self) -> BinopResult {
+        match self {
+            Value::Boolean(b) => Ok(Value::Boolean(!b)),
+            v => Err(format!("Unsupported operation not for {:?}", v)),
+        }
+    }
+
+    pub fn and(&self, right: Value) -> BinopResult {
+        match (self, right) {
+            (&Value::Boolean(lbool), Value::Boolean(rbool)) => Ok(Value::Boolean(lbool && rbool)),
+            (l, r) => Err(format!("Unsupported operation and for {:?} and {:?}", l, r)),
+        }
+    }
+
+    pub fn or(&self, right: Value) -> BinopResult {
+        match (self, right) {
+            (&Value::Boolean(lbool), Value::Boolean(rbool)) => Ok(Value::Boolean(lbool || rbool)),
+            (l, r) => Err(format!("Unsupported operation or for {:?} and {:?}", l, r)),
+        }
+    }
+
+    pub fn from_usize(number: usize) -> Value {
+        Value::Number(Number::from(Ratio::from_integer(BigInt::from(number))))
+    }
+
+    // Converts a number to a position in a list of the given length.
+    pub fn to_list_index(&self, length: usize) -> Option<usize> {
+        match self {
+            Value::Number(number) if number.is_integer() => number
+                .to_usize()
+                .and_then(|index| if index < length { Some(index) } else { None }),
+            _ => None,
+        }
+    }
+
     pub fn val_eq(&self, right: &Value) -> BinopResult {
         Ok(Value::Boolean(self == right))
     }
 
+    pub fn val_ne(&self, right: &Value) -> BinopResult {
+        Ok(Value::Boolean(self != right))
+    }
+
     pub fn val_gt(&self, right: &Value) -> BinopResult {
         Ok(Value::Boolean(self > right))
     }
@@ -191,9 +342,55 @@ impl Value {
     }
 }
 
+// Renders values the way they would be written in a program.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Number(number) => write!(f, "{}", number),
+            Value::CharString(str) => {
+                write!(f, "\"")?;
+                for c in str.chars() {
+                    match c {
+                        '"' => write!(f, "\\\"")?,
+                        '\\' => write!(f, "\\\\")?,
+                        '\n' => write!(f, "\\n")?,
+                        '\r' => write!(f, "\\r")?,
+                        '\t' => write!(f, "\\t")?,
+                        '\0' => write!(f, "\\0")?,
+                        c => write!(f, "{}", c)?,
+                    }
+                }
+                write!(f, "\"")
+            }
+            Value::Boolean(b) => write!(f, "{}", b),
+            Value::Map(map) => {
+                let map = map.borrow();
+                if map.is_empty() {
+                    return write!(f, "{{}}");
+                }
+                let pairs = map
+                    .iter()
+                    .map(|(key, value)| format!("{} => {}", key, value))
+                    .collect::<Vec<_>>();
+                write!(f, "{{ {} }}", pairs.join(", "))
+            }
+            Value::List(list) => {
+                let items = list
+                    .borrow()
+                    .iter()
+                    .map(|item| item.to_string())
+                    .collect::<Vec<_>>();
+                write!(f, "[{}]", items.join(", "))
+            }
+            Value::Closure(args, _) => write!(f, "fn({})", args.join(", ")),
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use test_helpers::*;
+    use value::{Value, POW_LIMIT_BITS};
 
     #[test]
     fn sub() {
@@ -256,6 +453,8 @@ mod test {
         assert_eq!(Ok(v_string("tototot")), v_string("to").mul(v_number(7, 2)));
         assert_eq!(Ok(v_string("tot")), v_string("toto").mul(v_number(3, 4)));
         assert_eq!(Ok(v_string("")), v_string("toto").mul(v_number(0, 1)));
+        assert_err!(v_string("ab").mul(v_number(-3, 2)));
+        assert_err!(v_number(-1, 1).mul(v_string("ab")));
         // Number * Boolean
         assert_eq!(Ok(v_number(0, 1)), v_number(1, 1).mul(v_bool(false)));
         assert_eq!(Ok(v_number(1, 1)), v_number(1, 1).mul(v_bool(true)));
@@ -313,6 +512,11 @@ mod test {
         // TODO: Boolean * Closure
         // TODO: Closure * Boolean
 
+        // Map holding values that can't be multiplied
+        let map = v_map(vec![(v_string("a"), v_list(vec![v_number(1, 1)]))]);
+        assert_err!(map.mul(v_number(2, 1)));
+        assert_err!(v_bool(true).mul(map));
+
         // Map * Map
     }
 
@@ -332,6 +536,7 @@ mod test {
         assert_eq!(Ok(v_string("tototot")), v_string("to").div(v_number(2, 7)));
         assert_eq!(Ok(v_string("tot")), v_string("toto").div(v_number(4, 3)));
         assert_err!(v_string("toto").div(v_number(0, 1)));
+        assert_err!(v_string("ab").div(v_number(-2, 1)));
         // CharString / CharString
         assert_err!(v_string("hello ").div(v_string("world")));
         // TODO: CharString / Boolean
@@ -345,6 +550,73 @@ mod test {
         assert_err!(v_map(vec![]).div(v_map(vec![])));
     }
 
+    #[test]
+    fn floor_div() {
+        // Number // Number
+        assert_eq!(Ok(v_number(3, 1)), v_number(7, 1).floor_div(v_number(2, 1)));
+        assert_eq!(Ok(v_number(-4, 1)), v_number(-7, 1).floor_div(v_number(2, 1)));
+        assert_eq!(Ok(v_number(2, 1)), v_number(5, 2).floor_div(v_number(1, 1)));
+        assert_err!(v_number(8, 1).floor_div(v_number(0, 1)));
+        assert_err!(v_number(8, 1).floor_div(v_string("toto")));
+        // CharString // Number
+        assert_eq!(Ok(v_string("ab")), v_string("abcde").floor_div(v_number(2, 1)));
+        assert_eq!(Ok(v_string("")), v_string("ab").floor_div(v_number(3, 1)));
+        assert_err!(v_string("toto").floor_div(v_number(0, 1)));
+        assert_err!(v_string("toto").floor_div(v_number(1, 2)));
+        assert_err!(v_string("toto").floor_div(v_number(-2, 1)));
+        // Boolean // Boolean
+        assert_err!(v_bool(true).floor_div(v_bool(true)));
+        // Map // Number
+        assert_err!(v_map(vec![]).floor_div(v_number(1, 1)));
+    }
+
+    #[test]
+    fn rem() {
+        // Number % Number
+        assert_eq!(Ok(v_number(1, 1)), v_number(7, 1).rem(v_number(3, 1)));
+        assert_eq!(Ok(v_number(2, 1)), v_number(-7, 1).rem(v_number(3, 1)));
+        assert_eq!(Ok(v_number(-2, 1)), v_number(7, 1).rem(v_number(-3, 1)));
+        assert_eq!(Ok(v_number(1, 2)), v_number(5, 2).rem(v_number(1, 1)));
+        assert_eq!(Ok(v_number(0, 1)), v_number(15, 1).rem(v_number(5, 1)));
+        assert_err!(v_number(8, 1).rem(v_number(0, 1)));
+        assert_err!(v_number(8, 1).rem(v_string("toto")));
+        // CharString % Number
+        assert_eq!(Ok(v_string("e")), v_string("abcde").rem(v_number(2, 1)));
+        assert_eq!(Ok(v_string("")), v_string("abcd").rem(v_number(2, 1)));
+        assert_err!(v_string("toto").rem(v_number(0, 1)));
+        assert_err!(v_string("toto").rem(v_number(3, 2)));
+        // Boolean % Boolean
+        assert_err!(v_bool(true).rem(v_bool(true)));
+        // Map % Number
+        assert_err!(v_map(vec![]).rem(v_number(1, 1)));
+    }
+
+    #[test]
+    fn pow() {
+        // Number ** Number
+        assert_eq!(Ok(v_number(8, 1)), v_number(2, 1).pow(v_number(3, 1)));
+        assert_eq!(Ok(v_number(1, 1)), v_number(2, 1).pow(v_number(0, 1)));
+        assert_eq!(Ok(v_number(1, 8)), v_number(2, 1).pow(v_number(-3, 1)));
+        assert_eq!(Ok(v_number(9, 4)), v_number(3, 2).pow(v_number(2, 1)));
+        assert_eq!(Ok(v_number(-8, 1)), v_number(-2, 1).pow(v_number(3, 1)));
+        assert_eq!(Ok(v_number(4, 9)), v_number(-3, 2).pow(v_number(-2, 1)));
+        assert_eq!(Ok(v_number(-1, 2)), v_number(-2, 1).pow(v_number(-1, 1)));
+        assert_err!(v_number(2, 1).pow(v_number(1, 2)));
+        assert_err!(v_number(0, 1).pow(v_number(-1, 1)));
+        assert_err!(v_number(2, 1).pow(v_number(1000000000, 1)));
+        assert_err!(v_number(1, 2).pow(v_number(-1000000000, 1)));
+        assert_eq!(
+            Ok(v_number(1, 1)),
+            v_number(-1, 1).pow(v_number(1000000000, 1))
+        );
+        assert_eq!(Ok(v_number(0, 1)), v_number(0, 1).pow(v_number(1000000000, 1)));
+        assert!(v_number(2, 1).pow(v_number(POW_LIMIT_BITS as i64, 1)).is_ok());
+        // CharString ** Number
+        assert_err!(v_string("toto").pow(v_number(2, 1)));
+        // Map ** Number
+        assert_err!(v_map(vec![]).pow(v_number(2, 1)));
+    }
+
     #[test]
     fn add() {
         // Numbers
@@ -374,6 +646,97 @@ mod test {
                 .add(v_map(vec![(v_number(2, 1), v_number(2, 1))]))
         );
         assert_err!(v_map(vec![(v_number(1, 1), v_number(1, 1))]).add(v_number(1, 1)));
+        // List
+        assert_eq!(
+            Ok(v_list(vec![v_number(1, 1), v_number(2, 1), v_number(3, 1)])),
+            v_list(vec![v_number(1, 1)]).add(v_list(vec![v_number(2, 1), v_number(3, 1)]))
+        );
+        assert_eq!(Ok(v_list(vec![])), v_list(vec![]).add(v_list(vec![])));
+        assert_err!(v_list(vec![]).add(v_number(1, 1)));
+        assert_err!(v_list(vec![]).add(v_map(vec![])));
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!("3/2", v_number(3, 2).to_string());
+        assert_eq!("-1", v_number(-1, 1).to_string());
+        assert_eq!(r#""a \"b\"\n""#, v_string("a \"b\"\n").to_string());
+        assert_eq!("true", v_bool(true).to_string());
+        assert_eq!("{}", v_map(vec![]).to_string());
+        assert_eq!(
+            r#"{ 1 => [], "a" => [true, "b"] }"#,
+            v_map(vec![
+                (v_string("a"), v_list(vec![v_bool(true), v_string("b")])),
+                (v_number(1, 1), v_list(vec![])),
+            ]).to_string()
+        );
+        assert_eq!(
+            "fn(a, b)",
+            v_closure(vec!["a".to_owned(), "b".to_owned()], vec![], None).to_string()
+        );
+    }
+
+    #[test]
+    fn from_usize() {
+        assert_eq!(v_number(0, 1), Value::from_usize(0));
+        assert_eq!(v_number(42, 1), Value::from_usize(42));
+    }
+
+    #[test]
+    fn to_list_index() {
+        assert_eq!(Some(0), v_number(0, 1).to_list_index(2));
+        assert_eq!(Some(1), v_number(1, 1).to_list_index(2));
+        assert_eq!(None, v_number(2, 1).to_list_index(2));
+        assert_eq!(None, v_number(-1, 1).to_list_index(2));
+        assert_eq!(None, v_number(1, 2).to_list_index(2));
+        assert_eq!(None, v_string("0").to_list_index(2));
+    }
+
+    #[test]
+    fn neg() {
+        assert_eq!(Ok(v_number(-1, 2)), v_number(1, 2).neg());
+        assert_eq!(Ok(v_number(3, 1)), v_number(-3, 1).neg());
+        assert_err!(v_string("toto").neg());
+        assert_err!(v_bool(true).neg());
+        assert_err!(v_map(vec![]).neg());
+    }
+
+    #[test]
+    fn not() {
+        assert_eq!(Ok(v_bool(false)), v_bool(true).not());
+        assert_eq!(Ok(v_bool(true)), v_bool(false).not());
+        assert_err!(v_number(1, 1).not());
+        assert_err!(v_string("").not());
+        assert_err!(v_map(vec![]).not());
+    }
+
+    #[test]
+    fn and() {
+        assert_eq!(Ok(v_bool(true)), v_bool(true).and(v_bool(true)));
+        assert_eq!(Ok(v_bool(false)), v_bool(true).and(v_bool(false)));
+        assert_eq!(Ok(v_bool(false)), v_bool(false).and(v_bool(true)));
+        assert_eq!(Ok(v_bool(false)), v_bool(false).and(v_bool(false)));
+        assert_err!(v_bool(true).and(v_number(1, 1)));
+        assert_err!(v_number(1, 1).and(v_bool(true)));
+        assert_err!(v_string("toto").and(v_string("toto")));
+    }
+
+    #[test]
+    fn or() {
+        assert_eq!(Ok(v_bool(true)), v_bool(true).or(v_bool(true)));
+        assert_eq!(Ok(v_bool(true)), v_bool(true).or(v_bool(false)));
+        assert_eq!(Ok(v_bool(true)), v_bool(false).or(v_bool(true)));
+        assert_eq!(Ok(v_bool(false)), v_bool(false).or(v_bool(false)));
+        assert_err!(v_bool(false).or(v_number(1, 1)));
+        assert_err!(v_map(vec![]).or(v_bool(false)));
+    }
+
+    #[test]
+    fn ne() {
+        assert_eq!(Ok(v_bool(false)), v_number(1, 1).val_ne(&v_number(1, 1)));
+        assert_eq!(Ok(v_bool(true)), v_number(2, 1).val_ne(&v_number(1, 1)));
+        assert_eq!(Ok(v_bool(true)), v_number(2, 1).val_ne(&v_string("toto")));
+        assert_eq!(Ok(v_bool(false)), v_string("toto").val_ne(&v_string("toto")));
     }
 
     #[test]
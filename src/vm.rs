@@ -3,144 +3,381 @@ use binding_map::BindingMap;
 use closure::Closure;
 use compiler::*;
 use grammar::*;
+use handler_table::HandlerTable;
 use instructions::*;
 use native::find_lib;
 use native::FileDescriptorMap;
-use std::fs;
-use std::fs::File;
-use std::io::Write;
+use parse_error::ParseError;
+use source_map::{Location, Source, SourceMap};
+use trace::{Trace, TraceEntry, TraceStep};
+use uncaught::{UncaughtEntry, UncaughtException, UncaughtPolicy, UNCAUGHT_LIMIT};
 use value::Value;
 
-use exception_handler::ExceptionHandler;
+use exception_handler::{ExceptionHandler, Guard, HandlerLookup};
 
 use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::mem;
 use std::rc::Rc;
 
+fn error_value(error: &str, fields: Vec<(&str, Value)>) -> Value {
+    let map = fields
+        .into_iter()
+        .map(|(key, value)| (Value::CharString(key.to_owned()), value))
+        .chain(vec![(
+            Value::CharString("error".to_owned()),
+            Value::CharString(error.to_owned()),
+        )])
+        .collect();
+    Value::Map(Rc::new(RefCell::new(map)))
+}
+
+fn key_error(key: Value, target: Value) -> Value {
+    error_value(
+        "key_error",
+        vec![
+            ("message", Value::CharString(format!("No value for key {}", key))),
+            ("key", key),
+            ("target", target),
+        ],
+    )
+}
+
+fn rescue_error() -> Value {
+    error_value(
+        "rescue_error",
+        vec![(
+            "message",
+            Value::CharString("Only rescue blocks can reraise or propagate".to_owned()),
+        )],
+    )
+}
+
+fn index_type_error(key: Value, target: Value) -> Value {
+    error_value(
+        "type_error",
+        vec![
+            (
+                "message",
+                Value::CharString("Only maps and lists can be indexed".to_owned()),
+            ),
+            ("key", key),
+            ("target", target),
+        ],
+    )
+}
+
 #[derive(Clone, Eq, Debug, PartialEq)]
 struct Frame {
     bindings: BindingMap,
-    exception_handlers: Vec<ExceptionHandler>,
+    exception_handlers: RefCell<HandlerTable>,
+    // Where the closure running in this frame was defined.
+    closure_span: Option<Span>,
+    // The raises that led to this frame.
+    trace: Trace,
+    // The raise the rescue block running in this frame, or that called the
+    // function running in it, is for.
+    rescued: Option<Rc<Rescued>>,
+    // The next frame raises look for handlers in. Frames without handlers are
+    // skipped, as there's nothing left to find in them.
+    caller: RefCell<Option<Rc<Frame>>>,
 }
 
 impl Frame {
-    pub fn new(bindings: BindingMap) -> Frame {
+    pub fn new(
+        bindings: BindingMap,
+        closure_span: Option<Span>,
+        trace: Trace,
+        caller: Option<Rc<Frame>>,
+    ) -> Frame {
         Frame {
-            bindings: bindings,
-            exception_handlers: Vec::new(),
+            bindings,
+            exception_handlers: RefCell::new(HandlerTable::new()),
+            closure_span,
+            trace,
+            rescued: None,
+            caller: RefCell::new(caller),
+        }
+    }
+
+    fn has_handlers(&self) -> bool {
+        !self.exception_handlers.borrow().is_empty()
+    }
+
+    // This frame if raises can still find handlers in it, its caller otherwise.
+    fn reachable(frame: &Rc<Frame>) -> Option<Rc<Frame>> {
+        match frame.has_handlers() {
+            true => Some(frame.clone()),
+            false => frame.caller.borrow().clone(),
+        }
+    }
+}
+
+// Callers are dropped one at a time, as dropping a long chain of frames
+// recursively could overflow the stack.
+impl Drop for Frame {
+    fn drop(&mut self) {
+        self.rescued.take();
+        let mut caller = self.caller.borrow_mut().take();
+        while let Some(frame) = caller {
+            caller = match Rc::try_unwrap(frame) {
+                Ok(frame) => frame.caller.borrow_mut().take(),
+                Err(_) => None,
+            };
         }
     }
 }
 
+// A rescued value, along with the handler that rescued it and the frame that
+// handler was registered in, so that the search can carry on after it.
+#[derive(Clone, Eq, Debug, PartialEq)]
+struct Rescued {
+    value: Value,
+    frame: Rc<Frame>,
+    handler: Rc<ExceptionHandler>,
+}
+
 #[derive(Clone, Eq, Debug, PartialEq)]
 pub struct Vm {
     instructions: Rc<InstructionSequence>,
+    source_map: Rc<SourceMap>,
+    source: Rc<Source>,
     pc: usize,
     stack: Vec<Value>,
-    frames: Vec<Frame>,
+    frame: Rc<Frame>,
+    handler_lookup: HandlerLookup,
+    uncaught_policy: UncaughtPolicy,
+    uncaught_exceptions: VecDeque<UncaughtEntry>,
+    uncaught_limit: usize,
+    dropped_uncaught_exceptions: usize,
+    last_trace: Trace,
+    halted: bool,
+    // Whether the instructions being run are those of a guard.
+    running_guard: bool,
+    // How many instructions have been run, for benchmarks. Only counted in
+    // tests, so that the dispatch loop doesn't pay for it.
+    #[cfg(test)]
+    executed: u64,
     pub file_descriptors: FileDescriptorMap,
 }
 
 impl Vm {
-    pub fn new(source: &str) -> Vm {
-        let stmts = statements(source);
-        let instructions = compile(&stmts.unwrap());
-        let map = BindingMap::new(None);
-        let frame = Frame::new(map);
+    #[cfg(test)]
+    pub fn new(source: &str) -> Result<Vm, ParseError> {
+        Vm::with_file_name("<source>", source)
+    }
+
+    pub fn with_file_name(file_name: &str, source: &str) -> Result<Vm, ParseError> {
+        let stmts = statements(source).map_err(|err| ParseError::new(file_name, source, &err))?;
+        let (names, instructions, source_map) = compile(&stmts);
+        let map = BindingMap::new(&names, None);
+        let frame = Frame::new(map, None, Trace::new(), None);
 
         let vm = Vm {
             instructions: Rc::new(instructions),
+            source_map: Rc::new(source_map),
+            source: Rc::new(Source::new(file_name, source)),
             pc: 0,
             stack: Vec::new(),
-            frames: vec![frame],
+            frame: Rc::new(frame),
+            handler_lookup: HandlerLookup::Callers,
+            uncaught_policy: UncaughtPolicy::Ignore,
+            uncaught_exceptions: VecDeque::new(),
+            uncaught_limit: UNCAUGHT_LIMIT,
+            dropped_uncaught_exceptions: 0,
+            last_trace: Trace::new(),
+            halted: false,
+            running_guard: false,
+            #[cfg(test)]
+            executed: 0,
             file_descriptors: FileDescriptorMap::new(),
         };
-        vm
+        Ok(vm)
     }
 
+    #[cfg(test)]
     pub fn empty() -> Vm {
-        let map = BindingMap::new(None);
-        let frame = Frame::new(map);
+        let map = BindingMap::new(&Rc::new(vec![]), None);
+        let frame = Frame::new(map, None, Trace::new(), None);
         let vm = Vm {
             instructions: Rc::new(vec![]),
+            source_map: Rc::new(SourceMap::new()),
+            source: Rc::new(Source::new("<empty>", "")),
             pc: 0,
             stack: Vec::new(),
-            frames: vec![frame],
+            frame: Rc::new(frame),
+            handler_lookup: HandlerLookup::Callers,
+            uncaught_policy: UncaughtPolicy::Ignore,
+            uncaught_exceptions: VecDeque::new(),
+            uncaught_limit: UNCAUGHT_LIMIT,
+            dropped_uncaught_exceptions: 0,
+            last_trace: Trace::new(),
+            halted: false,
+            running_guard: false,
+            #[cfg(test)]
+            executed: 0,
             file_descriptors: FileDescriptorMap::new(),
         };
         vm
     }
 
-    pub fn run<'b>(&'b mut self) {
-        loop {
-            let insn_result = Vm::next_instruction(self);
-            let instruction;
+    pub fn set_handler_lookup(&mut self, lookup: HandlerLookup) {
+        self.handler_lookup = lookup;
+    }
 
-            instruction = if let Some(i) = insn_result {
-                trace!("next instruction: {:?}", i);
-                i
-            } else {
-                trace!("instruction {:?} not found, terminating", insn_result);
-                break;
+    pub fn set_uncaught_policy(&mut self, policy: UncaughtPolicy) {
+        self.uncaught_policy = policy;
+    }
+
+    // How many uncaught exceptions to keep, `UNCAUGHT_LIMIT` by default.
+    pub fn set_uncaught_limit(&mut self, limit: usize) {
+        self.uncaught_limit = limit;
+        while self.uncaught_exceptions.len() > limit {
+            self.uncaught_exceptions.pop_front();
+            self.dropped_uncaught_exceptions += 1;
+        }
+    }
+
+    // The raised values that no handler matched, in the order they were
+    // raised. Only the most recent ones are kept, up to the uncaught limit,
+    // so that programs relying on ignored raises run in bounded memory.
+    pub fn uncaught_exceptions(&self) -> Vec<UncaughtException> {
+        self.uncaught_exceptions
+            .iter()
+            .map(|entry| self.uncaught_exception(entry))
+            .collect()
+    }
+
+    fn uncaught_exception(&self, entry: &UncaughtEntry) -> UncaughtException {
+        UncaughtException::new(
+            entry.value.clone(),
+            entry.location.map(|span| self.source.location(span)),
+        )
+    }
+
+    // How many uncaught exceptions were dropped to make room for newer ones.
+    pub fn dropped_uncaught_exceptions(&self) -> usize {
+        self.dropped_uncaught_exceptions
+    }
+
+    pub fn run<'b>(&'b mut self) {
+        while !self.halted {
+            // The sequence is held on to while its instruction runs, as calls
+            // and raises replace the one the VM runs.
+            let instructions = self.instructions.clone();
+            let instruction = match instructions.get(self.pc) {
+                Some(instruction) => instruction,
+                None => {
+                    trace!("no instruction at {}, terminating", self.pc);
+                    break;
+                }
             };
+            trace!("next instruction: {:?}", instruction);
+            self.pc += 1;
+            #[cfg(test)]
+            {
+                self.executed += 1;
+            }
 
-            match instruction {
+            match *instruction {
                 Instruction::Clear => self.stack.clear(),
-                Instruction::Push(ref value) => {
-                    let top_bindings = &mut self.frames.last_mut().unwrap().bindings;
-                    self.stack.push(Vm::literal_to_value(value, top_bindings))
+                Instruction::Push(ref value) => self.stack.push(Vm::literal_to_value(value)),
+                Instruction::MakeClosure(ref args, ref names, ref instructions, ref source_map) => {
+                    let mut closure = Closure::new(
+                        instructions.clone(),
+                        source_map.clone(),
+                        names.clone(),
+                        &self.frame.bindings,
+                    );
+                    closure.span = self.current_span();
+                    self.stack.push(Value::Closure(args.clone(), Rc::new(closure)))
                 }
-                Instruction::Assign(ref binding_name) => {
+                Instruction::Assign(ref binding) => {
                     let value = self.stack.pop().unwrap();
-                    self.frames
-                        .last_mut()
-                        .unwrap()
-                        .bindings
-                        .assign(binding_name, value)
+                    let assigned = self.frame.bindings.set(binding, value);
+                    if let Err(message) = assigned {
+                        self.raise_error(error_value(
+                            "name_error",
+                            vec![
+                                ("message", Value::CharString(message)),
+                                ("name", Value::CharString(binding.name.to_owned())),
+                            ],
+                        ))
+                    }
                 }
-                Instruction::LocalAssign(ref binding_name) => {
+                Instruction::LocalAssign(slot) => {
                     let value = self.stack.pop().unwrap();
-                    self.frames
-                        .last_mut()
-                        .unwrap()
-                        .bindings
-                        .local_assign(binding_name, value)
+                    self.frame.bindings.set_local(slot, value)
                 }
                 Instruction::Call(arg_size) => {
-                    let closure_info = match self.stack.pop() {
-                        Some(Value::Closure(arg_names, closure)) => Ok((arg_names, closure)),
-                        Some(x) => Err(format!("expected a closure, got {:?}", x)),
-                        None => Err(format!("expected a closure, got None")),
-                    };
-
-                    let new_stack_length = { self.stack.len() - arg_size };
-                    let mut args = self.stack.split_off(new_stack_length);
-                    let (closure_args, closure) = match closure_info {
-                        Ok(info) => info,
-                        Err(m) => panic!(m),
+                    let (closure_args, closure) = match self.stack.pop().unwrap() {
+                        Value::Closure(arg_names, closure) => (arg_names, closure),
+                        value => {
+                            self.raise_error(error_value(
+                                "type_error",
+                                vec![
+                                    (
+                                        "message",
+                                        Value::CharString(
+                                            "Only functions can be called".to_owned(),
+                                        ),
+                                    ),
+                                    ("target", value),
+                                ],
+                            ));
+                            continue;
+                        }
                     };
                     if arg_size != closure_args.len() {
-                        panic!(
-                            "wrong number of arguments, expected {:?}, got {:?}",
-                            closure_args.len(),
-                            arg_size
-                        )
+                        let function = Value::Closure(closure_args.clone(), closure.clone());
+                        self.raise_error(error_value(
+                            "argument_error",
+                            vec![
+                                (
+                                    "message",
+                                    Value::CharString(format!(
+                                        "Wrong number of arguments, expected {}, got {}",
+                                        closure_args.len(),
+                                        arg_size
+                                    )),
+                                ),
+                                ("expected", Value::from_usize(closure_args.len())),
+                                ("given", Value::from_usize(arg_size)),
+                                ("target", function),
+                            ],
+                        ));
+                        continue;
                     };
-                    let local_bindings = (*closure_args)
-                        .clone()
-                        .into_iter()
-                        .rev()
-                        .map(|arg_name| (arg_name, args.pop().unwrap()))
-                        .collect();
 
+                    let new_stack_length = { self.stack.len() - arg_size };
+                    let args = self.stack.split_off(new_stack_length);
+
+                    let mut frame = Frame::new(
+                        closure.init_map(args),
+                        closure.span,
+                        self.frame.trace.clone(),
+                        Frame::reachable(&self.frame),
+                    );
+                    frame.rescued = self.frame.rescued.clone();
                     self.reset_instructions(
                         closure.instructions.clone(),
-                        Some(closure.init_map(local_bindings)),
+                        closure.source_map.clone(),
+                        Some(frame),
                     );
                 }
-                Instruction::Fetch(ref binding_name) => {
-                    let value = self.fetch(binding_name).unwrap();
-                    self.stack.push(value);
-                }
+                Instruction::Fetch(ref binding) => match self.frame.bindings.get(binding) {
+                    Some(value) => self.stack.push(value),
+                    None => self.raise_error(error_value(
+                        "name_error",
+                        vec![
+                            (
+                                "message",
+                                Value::CharString(format!("No such binding {}", binding.name)),
+                            ),
+                            ("name", Value::CharString(binding.name.to_owned())),
+                        ],
+                    )),
+                },
                 Instruction::MakeMap(size) => {
                     let map = (0..size)
                         .into_iter()
@@ -152,194 +389,489 @@ impl Vm {
                         .collect();
                     self.stack.push(Value::Map(Rc::new(RefCell::new(map))))
                 }
-                Instruction::Rescue(ref pattern, ref iseq) => {
-                    let top_bindings = { &mut self.frames.last_mut().unwrap().bindings.clone() };
-                    let closure = Closure::new(iseq.clone(), top_bindings);
-                    self.frames
-                        .last_mut()
-                        .unwrap()
-                        .exception_handlers
-                        .push(ExceptionHandler::new(pattern.clone(), closure))
+                Instruction::MakeList(size) => {
+                    let new_stack_length = self.stack.len() - size;
+                    let items = self.stack.split_off(new_stack_length);
+                    self.stack.push(Value::List(Rc::new(RefCell::new(items))))
+                }
+                Instruction::Rescue(ref pattern, ref guard, ref names, ref iseq, ref source_map) => {
+                    let mut closure = Closure::new(
+                        iseq.clone(),
+                        source_map.clone(),
+                        names.clone(),
+                        &self.frame.bindings,
+                    );
+                    closure.span = self.current_span();
+                    let mut handler = ExceptionHandler::new(pattern.clone(), closure);
+                    handler.guard = guard.clone();
+                    self.register_handler(Rc::new(handler));
                 }
                 Instruction::Raise => {
                     let raised_value = self.stack.pop().unwrap();
                     self.raise(raised_value);
                 }
-                Instruction::BinOp(op) => {
+                Instruction::Rescued => match self.frame.rescued.clone() {
+                    Some(rescued) => self.stack.push(rescued.value.clone()),
+                    None => self.raise_error(rescue_error()),
+                },
+                Instruction::Reraise => {
+                    let raised_value = self.stack.pop().unwrap();
+                    self.reraise(raised_value, false);
+                }
+                Instruction::Propagate => {
+                    let raised_value = self.stack.pop().unwrap();
+                    self.reraise(raised_value, true);
+                }
+                Instruction::BinOp(ref op) => {
                     let right = self.stack.pop().unwrap();
                     let left = self.stack.pop().unwrap();
 
-                    let binop_result = match op {
-                        Op::Add => left.add(right),
-                        Op::Sub => left.sub(right),
-                        Op::Mul => left.mul(right),
-                        Op::Div => left.div(right),
-                        Op::Eq => left.val_eq(&right),
-                        Op::GtEq => {
-                            match left.val_eq(&right) {
-                                Ok(Value::Boolean(false)) => left.val_gt(&right),
-                                yes => yes,
-                            }
-                        }
-                        Op::Gt => left.val_gt(&right),
-                        Op::LtEq => {
-                            match left.val_eq(&right) {
-                                Ok(Value::Boolean(false)) => left.val_lt(&right),
-                                yes => yes,
-                            }
-                        }
-                        Op::Lt => left.val_lt(&right),
-                    };
+                    match Vm::binop(op, &left, &right) {
+                        Ok(result) => self.stack.push(result),
+                        Err(message) => self.raise_error(error_value(
+                            "type_error",
+                            vec![
+                                ("message", Value::CharString(message)),
+                                ("left", left),
+                                ("right", right),
+                            ],
+                        )),
+                    }
+                }
+                Instruction::UnaryOp(ref op) => {
+                    let operand = self.stack.pop().unwrap();
 
-                    if let Ok(result) = binop_result {
-                        self.stack.push(result);
-                    } else {
-                        // TODO: Raise
+                    match Vm::unaryop(op, &operand) {
+                        Ok(result) => self.stack.push(result),
+                        Err(message) => self.raise_error(error_value(
+                            "type_error",
+                            vec![
+                                ("message", Value::CharString(message)),
+                                ("operand", operand),
+                            ],
+                        )),
                     }
                 }
                 Instruction::IndexAccess => {
                     let property = self.stack.pop().unwrap();
                     let target = self.stack.pop().unwrap();
 
-                    match target {
-                        Value::Map(ref map) => {
-                            if let Some(value) = map.borrow().get(&property) {
-                                self.stack.push((*value).clone());
-                            } else {
-                                panic!("no value for {:?}", target); // TODO: Raise
-                            }
-                        }
-                        v => panic!("can't use index access for {:?}", v), // TODO: Raise
-                    };
+                    match Vm::index(&target, &property) {
+                        Ok(Some(value)) => self.stack.push(value),
+                        Ok(None) => self.raise_error(key_error(property, target)),
+                        Err(()) => self.raise_error(index_type_error(property, target)),
+                    }
                 }
                 Instruction::IndexAssign => {
                     let value = self.stack.pop().unwrap();
                     let property = self.stack.pop().unwrap();
-                    let mut target = self.stack.pop().unwrap();
+                    let target = self.stack.pop().unwrap();
 
-                    match target {
-                        Value::Map(ref mut map) => {
-                            map.borrow_mut().insert(property, value);
+                    let assigned = match target {
+                        Value::Map(ref map) => {
+                            map.borrow_mut().insert(property.clone(), value);
+                            Ok(true)
+                        }
+                        Value::List(ref list) => {
+                            let length = list.borrow().len();
+                            match property.to_list_index(length) {
+                                Some(index) => {
+                                    list.borrow_mut()[index] = value;
+                                    Ok(true)
+                                }
+                                None => Ok(false),
+                            }
                         }
-                        v => panic!("can't use index access for {:?}", v), // TODO: Raise
+                        _ => Err(()),
                     };
+
+                    match assigned {
+                        Ok(true) => {}
+                        Ok(false) => self.raise_error(key_error(property, target)),
+                        Err(()) => self.raise_error(index_type_error(property, target)),
+                    }
                 }
                 Instruction::Import => {
                     let name = self.stack.pop().unwrap();
-                    if let Value::CharString(ref str) = name {
-                        if let Some(lib) = find_lib(str) {
-                            self.stack.push(lib.clone());
-                        }
-                    } else {
-                        panic!("import value must be a string"); // TODO: Raise
+                    let lib = match name {
+                        Value::CharString(ref str) => Ok(find_lib(str)),
+                        _ => Err(()),
+                    };
+
+                    match lib {
+                        Ok(Some(lib)) => self.stack.push(lib),
+                        Ok(None) => self.raise_error(error_value(
+                            "import_error",
+                            vec![
+                                ("message", Value::CharString("No such library".to_owned())),
+                                ("name", name),
+                            ],
+                        )),
+                        Err(()) => self.raise_error(error_value(
+                            "type_error",
+                            vec![
+                                (
+                                    "message",
+                                    Value::CharString("Import name must be a string".to_owned()),
+                                ),
+                                ("name", name),
+                            ],
+                        )),
                     }
                 }
-                Instruction::Native(native_fn) => {
+                Instruction::Native(ref native_fn) => {
                     trace!("Starting native code");
                     let instructions = native_fn.call(self);
                     trace!("Finished native code");
-                    self.reset_instructions(Rc::new(instructions), None)
+                    self.reset_instructions(Rc::new(instructions), Rc::new(SourceMap::new()), None)
                 }
             };
         }
     }
 
+    fn binop(op: &Op, left: &Value, right: &Value) -> Result<Value, String> {
+        match *op {
+            Op::Add => left.add(right.clone()),
+            Op::Sub => left.sub(right.clone()),
+            Op::Mul => left.mul(right.clone()),
+            Op::Div => left.div(right.clone()),
+            Op::FloorDiv => left.floor_div(right.clone()),
+            Op::Mod => left.rem(right.clone()),
+            Op::Pow => left.pow(right.clone()),
+            Op::And => left.and(right.clone()),
+            Op::Or => left.or(right.clone()),
+            Op::Eq => left.val_eq(right),
+            Op::NotEq => left.val_ne(right),
+            Op::GtEq => match left.val_eq(right) {
+                Ok(Value::Boolean(false)) => left.val_gt(right),
+                yes => yes,
+            },
+            Op::Gt => left.val_gt(right),
+            Op::LtEq => match left.val_eq(right) {
+                Ok(Value::Boolean(false)) => left.val_lt(right),
+                yes => yes,
+            },
+            Op::Lt => left.val_lt(right),
+        }
+    }
+
+    fn unaryop(op: &UnaryOp, operand: &Value) -> Result<Value, String> {
+        match *op {
+            UnaryOp::Neg => operand.neg(),
+            UnaryOp::Not => operand.not(),
+        }
+    }
+
+    // The value at `property`, or an error if the target can't be indexed.
+    fn index(target: &Value, property: &Value) -> Result<Option<Value>, ()> {
+        match target {
+            Value::Map(map) => Ok(map.borrow().get(property).cloned()),
+            Value::List(list) => {
+                let list = list.borrow();
+                Ok(property
+                    .to_list_index(list.len())
+                    .map(|index| list[index].clone()))
+            }
+            _ => Err(()),
+        }
+    }
+
+    // Guards run in the dispatch loop, in a frame and on a stack of their
+    // own, until their last instruction. Their errors aren't raised, they make
+    // the guard fail instead, as does any value other than `true`.
+    fn guard_passes(&mut self, guard: &Guard, bindings: BindingMap, span: Option<Span>) -> bool {
+        let frame = Frame::new(bindings, span, self.frame.trace.clone(), None);
+        let instructions = mem::replace(&mut self.instructions, guard.instructions.clone());
+        let source_map = mem::replace(&mut self.source_map, guard.source_map.clone());
+        let pc = mem::replace(&mut self.pc, 0);
+        let stack = mem::take(&mut self.stack);
+        let frame = mem::replace(&mut self.frame, Rc::new(frame));
+
+        self.running_guard = true;
+        self.run();
+        let passes = !self.halted && self.stack.pop() == Some(Value::Boolean(true));
+        self.running_guard = false;
+        self.halted = false;
+
+        self.instructions = instructions;
+        self.source_map = source_map;
+        self.pc = pc;
+        self.stack = stack;
+        self.frame = frame;
+        passes
+    }
+
     pub fn push(&mut self, value: Value) {
         self.stack.push(value);
     }
 
+    #[cfg(test)]
     pub fn pop(&mut self) -> Option<Value> {
         self.stack.pop()
     }
 
     pub fn fetch(&mut self, name: &String) -> Option<Value> {
-        self.bindings().fetch(name)
+        self.frame.bindings.fetch(name)
     }
 
+    #[cfg(test)]
     pub fn local_assign(&mut self, name: &String, value: Value) {
-        self.bindings().local_assign(name, value);
+        self.frame.bindings.local_assign(name, value);
     }
 
-    fn bindings(&mut self) -> &mut BindingMap {
-        &mut self.frames.last_mut().unwrap().bindings
+    // Errors raised by the VM itself abandon the statement that caused them
+    // when nothing rescues them, since its operands are missing from the stack.
+    fn raise_error(&mut self, error: Value) {
+        if self.running_guard {
+            debug!("Guard failed at {:?}: {}", self.current_location(), error);
+            self.halted = true;
+            return;
+        }
+        if !self.raise(error) {
+            self.abandon_statement();
+        }
     }
 
-    fn raise(&mut self, value: Value) {
-        let matched_handler = self
-            .frames
-            .iter()
-            .rev()
-            .filter_map(|frame| {
-                let handlers = frame
-                    .exception_handlers
-                    .iter()
-                    .filter_map(|handler| match handler.matches(value.clone()) {
-                        Some(bindings) => Some((handler, bindings)),
-                        None => None,
-                    })
-                    .collect::<Vec<_>>();
-
-                if handlers.is_empty() {
-                    return None;
-                }
+    fn abandon_statement(&mut self) {
+        while let Some(instruction) = self.instructions.get(self.pc) {
+            if let &Instruction::Clear = instruction {
+                break;
+            }
+            self.pc += 1;
+        }
+    }
+
+    fn raise(&mut self, value: Value) -> bool {
+        let frame = Frame::reachable(&self.frame);
+        self.raise_to(value, frame, 0)
+    }
+
+    // Raises a value from a rescue block to the handlers registered after it,
+    // or when propagating, to the callers of the frame it was registered in.
+    fn reraise(&mut self, value: Value, propagate: bool) {
+        let rescued = match self.frame.rescued.clone() {
+            Some(rescued) => rescued,
+            None => return self.raise_error(rescue_error()),
+        };
+
+        let (frame, first_handler) = match propagate {
+            true => (rescued.frame.caller.borrow().clone(), 0),
+            false => {
+                let handlers = rescued.frame.exception_handlers.borrow();
+                let next = handlers
+                    .position(&rescued.handler)
+                    .map_or(handlers.len(), |position| position + 1);
+                (Some(rescued.frame.clone()), next)
+            }
+        };
+        self.raise_to(value, frame, first_handler);
+    }
+
+    // Looks for a handler from the given frame's handler onwards, then in the
+    // callers of that frame.
+    fn raise_to(&mut self, value: Value, frame: Option<Rc<Frame>>, first_handler: usize) -> bool {
+        let mut entry =
+            TraceEntry::new(value.clone(), self.current_span(), self.frame.closure_span);
+        // Only built once a handler that wants the trace is tried.
+        let mut traced_value = None;
+
+        let frames = Vm::searched_frames(frame);
+        let mut matched_handler = None;
+        'frames: for (index, frame) in frames.iter().enumerate() {
+            let skipped = if index == 0 { first_handler } else { 0 };
+            // Copied, so that the table isn't borrowed while guards run.
+            let candidates: Vec<_> = frame
+                .exception_handlers
+                .borrow()
+                .candidates(&value, skipped)
+                .into_iter()
+                .cloned()
+                .collect();
 
-                trace!("found handlers: {:?}", handlers.len());
-                Some(handlers.first().unwrap().clone())
-            })
-            .take(1)
-            .collect::<Vec<_>>()
-            .first()
-            .map(|&(ref handler, ref bindings)| {
-                let mut map = BindingMap::new(Some(&handler.closure.parent_bindings));
+            for handler in candidates.into_iter() {
+                let matched_value = match handler.wants_trace() {
+                    true => traced_value
+                        .get_or_insert_with(|| {
+                            ExceptionHandler::with_trace(&value, self.trace_value(&entry))
+                        })
+                        .clone(),
+                    false => value.clone(),
+                };
+                let bindings = match handler.matches(matched_value) {
+                    Some(bindings) => bindings,
+                    None => continue,
+                };
+
+                let map = BindingMap::new(
+                    &handler.closure.names,
+                    Some(&handler.closure.parent_bindings),
+                );
                 for (key, value) in bindings.iter() {
                     map.local_assign(key, value.to_owned());
                 }
+                if let Some(ref guard) = handler.guard {
+                    if !self.guard_passes(guard, map.clone(), handler.closure.span) {
+                        continue;
+                    }
+                }
+
                 trace!("bindings: {:?}", bindings);
-                (handler.closure.instructions.clone(), map)
-            });
+                matched_handler = Some((
+                    handler.closure.instructions.clone(),
+                    handler.closure.source_map.clone(),
+                    handler.closure.span,
+                    map,
+                    frame.clone(),
+                    handler.clone(),
+                ));
+                break 'frames;
+            }
+        }
 
-        if let Some((instructions, map)) = matched_handler {
+        if let Some((instructions, source_map, handler_span, map, handler_frame, handler)) =
+            matched_handler
+        {
             trace!("instructions: {:?}", instructions);
-            self.reset_instructions(instructions, Some(map));
+            entry.handler = handler_span;
+            self.last_trace = self.frame.trace.extend(entry);
+            // Under caller lookup, the rescue block runs as if the frames
+            // between it and the raise were unwound.
+            let caller = match self.handler_lookup {
+                HandlerLookup::Callers => Some(handler_frame.clone()),
+                HandlerLookup::Global => Frame::reachable(&self.frame),
+            };
+            let mut frame = Frame::new(map, handler_span, self.last_trace.clone(), caller);
+            frame.rescued = Some(Rc::new(Rescued {
+                value,
+                frame: handler_frame,
+                handler,
+            }));
+            self.reset_instructions(instructions, source_map, Some(frame));
+            true
         } else {
-            debug!("Uncaught exception ignored: {:?}", value);
+            self.last_trace = self.frame.trace.extend(entry);
+            let entry = UncaughtEntry::new(value, self.current_span());
+            match self.uncaught_policy {
+                UncaughtPolicy::Ignore => debug!("Uncaught exception ignored: {}", entry.value),
+                UncaughtPolicy::Warn => eprintln!("{}", self.uncaught_exception(&entry)),
+                UncaughtPolicy::Strict => self.halted = true,
+            }
+            self.uncaught_exceptions.push_back(entry);
+            if self.uncaught_exceptions.len() > self.uncaught_limit {
+                self.uncaught_exceptions.pop_front();
+                self.dropped_uncaught_exceptions += 1;
+            }
+            false
+        }
+    }
+
+    // The raises that led to the most recent one, oldest first. Raises from a
+    // rescue block, or from functions it calls, continue the trace of the
+    // raise it rescued.
+    pub fn last_trace(&self) -> Vec<TraceStep> {
+        self.last_trace
+            .entries()
+            .into_iter()
+            .map(|entry| TraceStep::new(entry, &self.source))
+            .collect()
+    }
+
+    // The trace of a raise as handlers see it, ending with the given entry.
+    fn trace_value(&self, entry: &TraceEntry) -> Value {
+        let trace = self.frame.trace.extend(entry.clone());
+        let steps = trace
+            .entries()
+            .into_iter()
+            .map(|entry| TraceStep::new(entry, &self.source).to_value())
+            .collect();
+        Value::List(Rc::new(RefCell::new(steps)))
+    }
+
+    // The location of the instruction at `pc` in the sequence being run, if it
+    // was compiled from source.
+    pub fn location(&self, pc: usize) -> Option<Location> {
+        self.source_map
+            .span_at(pc)
+            .map(|span| self.source.location(span))
+    }
+
+    // The location of the instruction being run, `pc` having already moved
+    // past it.
+    pub fn current_location(&self) -> Option<Location> {
+        match self.pc {
+            0 => None,
+            pc => self.location(pc - 1),
+        }
+    }
+
+    fn current_span(&self) -> Option<Span> {
+        match self.pc {
+            0 => None,
+            pc => self.source_map.span_at(pc - 1),
+        }
+    }
+
+    // The frames a raise looks for handlers in, in order: the given frame and
+    // its callers.
+    fn searched_frames(mut frame: Option<Rc<Frame>>) -> Vec<Rc<Frame>> {
+        let mut frames = Vec::new();
+        while let Some(current) = frame {
+            frame = current.caller.borrow().clone();
+            frames.push(current);
+        }
+        frames
+    }
+
+    fn register_handler(&mut self, handler: Rc<ExceptionHandler>) {
+        if self.handler_lookup == HandlerLookup::Global {
+            self.drop_shadowed_handlers(&handler);
+        }
+        self.frame.exception_handlers.borrow_mut().push(handler);
+    }
+
+    // Under global lookup, the callers of a frame are every older frame with
+    // handlers, so a handler can never be reached once a newer one has the
+    // same pattern. Those handlers are dropped, along with the frames that are
+    // left without any.
+    fn drop_shadowed_handlers(&mut self, handler: &ExceptionHandler) {
+        let mut frame = self.frame.clone();
+        loop {
+            let older = match frame.caller.borrow().clone() {
+                Some(older) => older,
+                None => break,
+            };
+            older
+                .exception_handlers
+                .borrow_mut()
+                .retain(|older| !handler.shadows(older));
+            if older.has_handlers() {
+                frame = older;
+            } else {
+                *frame.caller.borrow_mut() = older.caller.borrow().clone();
+            }
         }
     }
 
     fn reset_instructions(
         &mut self,
         instructions: Rc<InstructionSequence>,
-        binding_map: Option<BindingMap>,
+        source_map: Rc<SourceMap>,
+        frame: Option<Frame>,
     ) {
-        if let Some(map) = binding_map {
-            self.frames.push(Frame::new(map));
+        if let Some(frame) = frame {
+            self.frame = Rc::new(frame);
         }
         self.instructions = instructions.clone();
+        self.source_map = source_map;
         self.pc = 0;
         trace!("instructions have been reset!");
     }
 
-    fn next_instruction(vm: &mut Vm) -> Option<Instruction> {
-        let instruction = match vm.instructions.get(vm.pc) {
-            Some(i) => Some(i.clone()),
-            None => None,
-        };
-
-        vm.pc += 1;
-
-        instruction
-    }
-
-    fn literal_to_value<'b>(literal: &'b Literal, top_bindings: &BindingMap) -> Value {
+    fn literal_to_value(literal: &Literal) -> Value {
         match literal {
-            &Literal::Number(ref num) => Value::Number(num.to_owned()),
-            &Literal::CharString(ref str) => Value::CharString(str.to_string()),
-            &Literal::Fn(ref args, ref statements) => {
-                // Statements should be compiled ahead of time
-                let closure = Closure::new(Rc::new(compile(&statements)), top_bindings);
-                Value::Closure(Rc::new(args.clone()), Rc::new(closure))
-            }
-            &Literal::Boolean(b) => Value::Boolean(b),
+            Literal::Number(num) => Value::Number(num.clone()),
+            Literal::CharString(str) => Value::CharString(str.to_string()),
+            Literal::Boolean(b) => Value::Boolean(*b),
             _ => panic!("not implemented literal_to_value for {:?}", literal),
         }
     }
@@ -348,6 +880,10 @@ impl Vm {
 #[cfg(test)]
 mod test {
     use super::*;
+    use std::fs;
+    use std::fs::File;
+    use std::io::Write;
+    use std::time::Instant;
     use test_helpers::*;
 
     #[test]
@@ -355,7 +891,7 @@ mod test {
         let mut vm = Vm::new(
             r#"let a = 1
         let b = { "a" => 1 }"#,
-        );
+        ).unwrap();
         vm.run();
         assert_eq!(
             v_number(1, 1),
@@ -365,6 +901,18 @@ mod test {
         assert_eq!(expected_map, vm.fetch(&"b".to_owned()).unwrap().to_owned())
     }
 
+    #[test]
+    fn locates_the_instruction_being_run() {
+        let mut vm = Vm::with_file_name("test.!", "let a = 1\nraise(a)").unwrap();
+        assert_eq!(None, vm.current_location());
+        vm.run();
+
+        let location = vm.location(5).unwrap();
+        assert_eq!("test.!:2:1", location.to_string());
+        assert_eq!("raise(a)", location.source_line);
+        assert_eq!(None, vm.location(6));
+    }
+
     #[test]
     fn function_call() {
         let source = r#"let a = ""
@@ -373,7 +921,7 @@ mod test {
             end
             x()"#;
 
-        let mut vm = Vm::new(source);
+        let mut vm = Vm::new(source).unwrap();
         vm.run();
         assert_eq!(
             v_number(1, 1),
@@ -391,7 +939,7 @@ mod test {
             end
             x(1, 2)"#;
 
-        let mut vm = Vm::new(source);
+        let mut vm = Vm::new(source).unwrap();
         vm.run();
         assert_eq!(
             v_number(1, 1),
@@ -399,34 +947,19 @@ mod test {
         );
         assert_eq!(
             v_number(2, 1),
-            vm.frames
-                .last()
-                .unwrap()
-                .bindings
-                .fetch(&"b".to_owned())
-                .unwrap()
-                .to_owned()
+            vm.fetch(&"b".to_owned()).unwrap().to_owned()
         )
     }
 
     #[test]
-    #[should_panic(expected = "expected a closure")]
-    fn calling_non_function() {
-        let source = r#"let x = ""
-            x()"#;
-        let mut vm = Vm::new(source);
-        vm.run();
-    }
-
-    #[test]
-    #[should_panic(expected = "wrong number of arguments")]
-    fn function_with_wrong_arg_count() {
-        let source = "let x = fn(a, b) do
-            end
-            x(1)";
+    fn syntax_errors_are_returned() {
+        let source = "let a = 1\nlet b = = 2";
 
-        let mut vm = Vm::new(source);
-        vm.run();
+        let error = Vm::with_file_name("test.!", source).unwrap_err();
+        assert_eq!("test.!", error.file_name);
+        assert_eq!(2, error.line);
+        assert_eq!(9, error.column);
+        assert_eq!("let b = = 2", error.source_line);
     }
 
     #[test]
@@ -440,7 +973,7 @@ mod test {
             end
             x(1)"#;
 
-        let mut vm = Vm::new(source);
+        let mut vm = Vm::new(source).unwrap();
         vm.run();
         assert_eq!(
             v_number(1, 1),
@@ -459,7 +992,7 @@ mod test {
             end
             x(2, 1)"#;
 
-        let mut vm = Vm::new(source);
+        let mut vm = Vm::new(source).unwrap();
         vm.run();
         assert_eq!(
             v_number(1, 1),
@@ -475,7 +1008,7 @@ mod test {
             let c = a["c"]
             let d = a + { "e" => 3 }"#;
 
-        let mut vm = Vm::new(source);
+        let mut vm = Vm::new(source).unwrap();
         vm.run();
 
         assert_eq!(
@@ -503,6 +1036,1006 @@ mod test {
         );
     }
 
+    #[test]
+    fn grouped_expressions() {
+        let source = r#"let a = (1 + 2) * 3
+            let b = 1 + 2 * 3
+            let m = { 2 => "two" }
+            let c = m[(a - b) * 1]"#;
+
+        let mut vm = Vm::new(source).unwrap();
+        vm.run();
+
+        assert_eq!(
+            v_number(9, 1),
+            vm.fetch(&"a".to_owned()).unwrap().to_owned()
+        );
+        assert_eq!(
+            v_number(7, 1),
+            vm.fetch(&"b".to_owned()).unwrap().to_owned()
+        );
+        assert_eq!(
+            v_string("two"),
+            vm.fetch(&"c".to_owned()).unwrap().to_owned()
+        );
+    }
+
+    #[test]
+    fn left_associative_arithmetics() {
+        let source = r#"let a = 10 - 3 - 2
+            let b = 8 / 4 / 2"#;
+
+        let mut vm = Vm::new(source).unwrap();
+        vm.run();
+
+        assert_eq!(
+            v_number(5, 1),
+            vm.fetch(&"a".to_owned()).unwrap().to_owned()
+        );
+        assert_eq!(
+            v_number(1, 1),
+            vm.fetch(&"b".to_owned()).unwrap().to_owned()
+        );
+    }
+
+    #[test]
+    fn negation() {
+        let source = r#"let a = 3
+            let b = -a
+            let c = 1 - -a * 2
+            let d = ""
+            rescue({"delta" => -1}) do
+              d = "caught"
+            end
+            raise({"delta" => -1})"#;
+
+        let mut vm = Vm::new(source).unwrap();
+        vm.run();
+
+        assert_eq!(
+            v_number(-3, 1),
+            vm.fetch(&"b".to_owned()).unwrap().to_owned()
+        );
+        assert_eq!(
+            v_number(7, 1),
+            vm.fetch(&"c".to_owned()).unwrap().to_owned()
+        );
+        assert_eq!(
+            v_string("caught"),
+            vm.fetch(&"d".to_owned()).unwrap().to_owned()
+        );
+    }
+
+    #[test]
+    fn repeating_strings_a_negative_number_of_times_raises_a_type_error() {
+        for operation in &[r#""ab" * -1.5"#, r#""ab" / -2"#] {
+            let source = format!(
+                r#"let right = ""
+                rescue({{"error" => "type_error", "left" => "ab", "right" => r}}) do
+                  right = r
+                end
+                let a = {}"#,
+                operation
+            );
+
+            let mut vm = Vm::new(&source).unwrap();
+            vm.run();
+
+            assert_ne!(v_string(""), vm.fetch(&"right".to_owned()).unwrap());
+        }
+    }
+
+    #[test]
+    fn modulo_floor_division_and_exponentiation() {
+        let source = r#"let a = 7 % 3
+            let b = -7 // 2
+            let c = 2 ** 3 ** 2
+            let d = 2 ** -2
+            let e = 15 % 5 == 0"#;
+
+        let mut vm = Vm::new(source).unwrap();
+        vm.run();
+
+        assert_eq!(v_number(1, 1), vm.fetch(&"a".to_owned()).unwrap());
+        assert_eq!(v_number(-4, 1), vm.fetch(&"b".to_owned()).unwrap());
+        assert_eq!(v_number(512, 1), vm.fetch(&"c".to_owned()).unwrap());
+        assert_eq!(v_number(1, 4), vm.fetch(&"d".to_owned()).unwrap());
+        assert_eq!(v_bool(true), vm.fetch(&"e".to_owned()).unwrap());
+    }
+
+    #[test]
+    fn boolean_logic() {
+        let source = r#"let a = true and not false
+            let b = false or 1 > 2
+            let c = 1 != 2 and "a" != "a" or true
+            let d = not 1 == 2"#;
+
+        let mut vm = Vm::new(source).unwrap();
+        vm.run();
+
+        assert_eq!(v_bool(true), vm.fetch(&"a".to_owned()).unwrap());
+        assert_eq!(v_bool(false), vm.fetch(&"b".to_owned()).unwrap());
+        assert_eq!(v_bool(true), vm.fetch(&"c".to_owned()).unwrap());
+        assert_eq!(v_bool(true), vm.fetch(&"d".to_owned()).unwrap());
+    }
+
+    #[test]
+    fn boolean_logic_on_non_booleans_raises_a_type_error() {
+        let source = r#"let left = ""
+            rescue({"error" => "type_error", "left" => l}) do
+              left = l
+            end
+            let a = 1 and true"#;
+
+        let mut vm = Vm::new(source).unwrap();
+        vm.run();
+        assert_eq!(v_number(1, 1), vm.fetch(&"left".to_owned()).unwrap());
+
+        let source = r#"let operand = ""
+            rescue({"error" => "type_error", "operand" => o}) do
+              operand = o
+            end
+            let b = not "toto""#;
+
+        let mut vm = Vm::new(source).unwrap();
+        vm.run();
+        assert_eq!(v_string("toto"), vm.fetch(&"operand".to_owned()).unwrap());
+    }
+
+    #[test]
+    fn uncaught_type_errors_abandon_the_statement() {
+        let source = r#"let a = 1
+            a = 1 or 2
+            let b = 2"#;
+
+        let mut vm = Vm::new(source).unwrap();
+        vm.run();
+
+        assert_eq!(v_number(1, 1), vm.fetch(&"a".to_owned()).unwrap());
+        assert_eq!(v_number(2, 1), vm.fetch(&"b".to_owned()).unwrap());
+    }
+
+    #[test]
+    fn missing_keys_raise_a_key_error() {
+        let source = r#"let key = ""
+            let target = ""
+            rescue({"error" => "key_error", "key" => k, "target" => t}) do
+              key = k
+              target = t
+            end
+            let map = {"a" => 1}
+            let b = map["b"]"#;
+
+        let mut vm = Vm::new(source).unwrap();
+        vm.run();
+        assert_eq!(v_string("b"), vm.fetch(&"key".to_owned()).unwrap());
+        assert_eq!(
+            v_map(vec![(v_string("a"), v_number(1, 1))]),
+            vm.fetch(&"target".to_owned()).unwrap()
+        );
+
+        let source = r#"let key = ""
+            rescue({"error" => "key_error", "key" => k}) do
+              key = k
+            end
+            let list = [1]
+            list[1] = 2"#;
+
+        let mut vm = Vm::new(source).unwrap();
+        vm.run();
+        assert_eq!(v_number(1, 1), vm.fetch(&"key".to_owned()).unwrap());
+    }
+
+    #[test]
+    fn indexing_other_values_raises_a_type_error() {
+        let source = r#"let target = ""
+            rescue({"error" => "type_error", "target" => t}) do
+              target = t
+            end
+            let a = 1
+            let b = a.b"#;
+
+        let mut vm = Vm::new(source).unwrap();
+        vm.run();
+        assert_eq!(v_number(1, 1), vm.fetch(&"target".to_owned()).unwrap());
+    }
+
+    #[test]
+    fn calling_other_values_raises_a_type_error() {
+        let source = r#"let target = ""
+            rescue({"error" => "type_error", "target" => t}) do
+              target = t
+            end
+            let a = "toto"
+            a(1)"#;
+
+        let mut vm = Vm::new(source).unwrap();
+        vm.run();
+        assert_eq!(v_string("toto"), vm.fetch(&"target".to_owned()).unwrap());
+    }
+
+    #[test]
+    fn wrong_argument_counts_raise_an_argument_error() {
+        let source = r#"let expected = 0
+            let given = 0
+            let called = false
+            rescue({"error" => "argument_error", "expected" => e, "given" => g}) do
+              expected = e
+              given = g
+            end
+            let f = fn(a, b) do
+              called = true
+            end
+            f(1)"#;
+
+        let mut vm = Vm::new(source).unwrap();
+        vm.run();
+        assert_eq!(v_number(2, 1), vm.fetch(&"expected".to_owned()).unwrap());
+        assert_eq!(v_number(1, 1), vm.fetch(&"given".to_owned()).unwrap());
+        assert_eq!(v_bool(false), vm.fetch(&"called".to_owned()).unwrap());
+    }
+
+    #[test]
+    fn failed_imports_raise_errors() {
+        let source = r#"let name = ""
+            rescue({"error" => "type_error", "name" => n}) do
+              name = n
+            end
+            let lib = import(1)"#;
+
+        let mut vm = Vm::new(source).unwrap();
+        vm.run();
+        assert_eq!(v_number(1, 1), vm.fetch(&"name".to_owned()).unwrap());
+
+        let source = r#"let name = ""
+            rescue({"error" => "import_error", "name" => n}) do
+              name = n
+            end
+            let lib = import("oops")"#;
+
+        let mut vm = Vm::new(source).unwrap();
+        vm.run();
+        assert_eq!(v_string("oops"), vm.fetch(&"name".to_owned()).unwrap());
+    }
+
+    #[test]
+    fn unknown_bindings_raise_a_name_error() {
+        let source = r#"let name = ""
+            rescue({"error" => "name_error", "name" => n}) do
+              name = n
+            end
+            toto = 1"#;
+
+        let mut vm = Vm::new(source).unwrap();
+        vm.run();
+        assert_eq!(v_string("toto"), vm.fetch(&"name".to_owned()).unwrap());
+
+        let source = r#"let name = ""
+            rescue({"error" => "name_error", "name" => n}) do
+              name = n
+            end
+            let a = titi"#;
+
+        let mut vm = Vm::new(source).unwrap();
+        vm.run();
+        assert_eq!(v_string("titi"), vm.fetch(&"name".to_owned()).unwrap());
+    }
+
+    #[test]
+    fn names_refer_to_the_nearest_binding_that_is_set() {
+        let source = r#"let a = 1
+            let f = fn() do
+              let b = a
+              let a = 2
+              let c = a
+              g([b, c])
+            end
+            let g = fn(seen) do
+              a = 3
+            end
+            f()"#;
+
+        let mut vm = Vm::new(source).unwrap();
+        vm.run();
+        assert_eq!(
+            v_list(vec![v_number(1, 1), v_number(2, 1)]),
+            vm.fetch(&"seen".to_owned()).unwrap()
+        );
+        assert_eq!(v_number(3, 1), vm.fetch(&"a".to_owned()).unwrap());
+        assert_eq!(None, vm.fetch(&"b".to_owned()));
+    }
+
+    #[test]
+    fn first_registered_handler_wins_whatever_its_pattern() {
+        let handlers = r#"let seen = []
+            rescue({ "n" => n }) when n > 5 do
+              seen = seen + ["big"]
+            end
+            rescue(value) when value == 1 do
+              seen = seen + ["one"]
+            end
+            rescue({ "n" => 2 }) do
+              seen = seen + ["two"]
+              reraise
+            end
+            rescue({ "n" => n }) do
+              seen = seen + [n]
+            end
+            "#;
+        let seen = |raise: &str| {
+            let mut vm = Vm::new(&(handlers.to_owned() + raise)).unwrap();
+            vm.run();
+            vm.fetch(&"seen".to_owned()).unwrap()
+        };
+
+        assert_eq!(
+            v_list(vec![v_string("two"), v_number(2, 1)]),
+            seen(r#"raise({ "n" => 2 })"#)
+        );
+        assert_eq!(
+            v_list(vec![v_string("big")]),
+            seen(r#"raise({ "n" => 9, "m" => 1 })"#)
+        );
+        assert_eq!(v_list(vec![v_string("one")]), seen("raise(1)"));
+    }
+
+    #[test]
+    fn uncaught_runtime_errors_abandon_the_statement() {
+        let source = r#"let a = 1
+            a = {}["b"]
+            toto = 2
+            a(1)
+            let b = import("oops")
+            let c = 2"#;
+
+        let mut vm = Vm::new(source).unwrap();
+        vm.run();
+
+        assert_eq!(v_number(1, 1), vm.fetch(&"a".to_owned()).unwrap());
+        assert_eq!(None, vm.fetch(&"b".to_owned()));
+        assert_eq!(v_number(2, 1), vm.fetch(&"c".to_owned()).unwrap());
+    }
+
+    #[test]
+    fn collects_uncaught_exceptions() {
+        let source = r#"raise(1)
+            let a = {}["b"]"#;
+
+        let mut vm = Vm::with_file_name("test.!", source).unwrap();
+        vm.run();
+
+        let exceptions = vm.uncaught_exceptions();
+        assert_eq!(2, exceptions.len());
+        assert_eq!(v_number(1, 1), exceptions[0].value);
+        assert_eq!(
+            "test.!:1:1",
+            exceptions[0].location.as_ref().unwrap().to_string()
+        );
+        match exceptions[1].value {
+            Value::Map(ref map) => assert_eq!(
+                Some(&v_string("key_error")),
+                map.borrow().get(&v_string("error"))
+            ),
+            ref value => panic!("expected an error map, got {:?}", value),
+        }
+        assert_eq!(
+            "test.!:2:21",
+            exceptions[1].location.as_ref().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn keeps_the_most_recent_uncaught_exceptions() {
+        let source = format!(
+            r#"rescue({{ "i" => {} }}) do
+            end
+            rescue({{ "i" => i }}) do
+              raise(i)
+              raise({{ "i" => i + 1 }})
+            end
+            raise({{ "i" => 0 }})"#,
+            UNCAUGHT_LIMIT + 5
+        );
+
+        let mut vm = Vm::new(&source).unwrap();
+        vm.run();
+
+        let exceptions = vm.uncaught_exceptions();
+        assert_eq!(UNCAUGHT_LIMIT, exceptions.len());
+        assert_eq!(5, vm.dropped_uncaught_exceptions());
+        assert_eq!(v_number(5, 1), exceptions[0].value);
+
+        let mut vm = Vm::new(&source).unwrap();
+        vm.set_uncaught_limit(10);
+        vm.run();
+
+        let exceptions = vm.uncaught_exceptions();
+        assert_eq!(10, exceptions.len());
+        assert_eq!(UNCAUGHT_LIMIT - 5, vm.dropped_uncaught_exceptions());
+        assert_eq!(v_number(UNCAUGHT_LIMIT as i64 - 5, 1), exceptions[0].value);
+    }
+
+    #[test]
+    fn strict_policy_stops_at_the_first_uncaught_exception() {
+        let source = r#"let a = 1
+            raise(a)
+            a = 2"#;
+
+        let mut vm = Vm::new(source).unwrap();
+        vm.set_uncaught_policy(UncaughtPolicy::Strict);
+        vm.run();
+
+        assert_eq!(v_number(1, 1), vm.fetch(&"a".to_owned()).unwrap());
+        assert_eq!(1, vm.uncaught_exceptions().len());
+
+        let mut vm = Vm::new(source).unwrap();
+        vm.set_uncaught_policy(UncaughtPolicy::Warn);
+        vm.run();
+
+        assert_eq!(v_number(2, 1), vm.fetch(&"a".to_owned()).unwrap());
+        assert_eq!(1, vm.uncaught_exceptions().len());
+    }
+
+    #[test]
+    fn traces_raises_through_handlers() {
+        let source = r#"let countdown = fn(k) do
+  rescue({ "k" => 0 }) do
+    raise("done")
+  end
+  rescue({ "k" => k }) do
+    raise({ "k" => k - 1 })
+  end
+  raise({ "k" => k })
+end
+countdown(2)"#;
+
+        let mut vm = Vm::with_file_name("test.!", source).unwrap();
+        vm.run();
+
+        let trace = vm.last_trace();
+        let describe = |location: &Option<Location>| location.as_ref().map(|l| l.to_string());
+        assert_eq!(4, trace.len());
+        assert_eq!(
+            vec![
+                v_map(vec![(v_string("k"), v_number(2, 1))]),
+                v_map(vec![(v_string("k"), v_number(1, 1))]),
+                v_map(vec![(v_string("k"), v_number(0, 1))]),
+                v_string("done"),
+            ],
+            trace
+                .iter()
+                .map(|step| step.value.clone())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(Some("test.!:8:3".to_owned()), describe(&trace[0].location));
+        assert_eq!(Some("test.!:1:17".to_owned()), describe(&trace[0].closure));
+        assert_eq!(Some("test.!:5:3".to_owned()), describe(&trace[0].handler));
+        assert_eq!(Some("test.!:6:5".to_owned()), describe(&trace[1].location));
+        assert_eq!(Some("test.!:5:3".to_owned()), describe(&trace[1].closure));
+        assert_eq!(Some("test.!:2:3".to_owned()), describe(&trace[2].handler));
+        assert_eq!(Some("test.!:3:5".to_owned()), describe(&trace[3].location));
+        assert_eq!(None, trace[3].handler);
+    }
+
+    #[test]
+    fn binds_traces_to_patterns_that_ask_for_them() {
+        let source = r#"let steps = 0
+let site = ""
+rescue({ "k" => 0, "__trace__" => t }) do
+  steps = t
+  site = t[1].location
+end
+rescue({ "k" => k }) do
+  raise({ "k" => k - 1 })
+end
+raise({ "k" => 1 })"#;
+
+        let mut vm = Vm::with_file_name("test.!", source).unwrap();
+        vm.run();
+
+        match vm.fetch(&"steps".to_owned()).unwrap() {
+            Value::List(ref steps) => assert_eq!(2, steps.borrow().len()),
+            value => panic!("expected a list of steps, got {:?}", value),
+        }
+        assert_eq!(
+            v_string("test.!:8:3"),
+            vm.fetch(&"site".to_owned()).unwrap()
+        );
+    }
+
+    #[test]
+    fn binds_traces_through_as_type_and_alternative_patterns() {
+        for pattern in &[
+            r#"{ "__trace__" => t } as v"#,
+            r#"map({ "__trace__" => t })"#,
+            r#"{ "k" => 2, "__trace__" => t } | { "__trace__" => t }"#,
+        ] {
+            let source = format!(
+                r#"let steps = 0
+rescue({}) do
+  steps = t
+end
+raise({{ "k" => 1 }})"#,
+                pattern
+            );
+
+            let mut vm = Vm::new(&source).unwrap();
+            vm.run();
+
+            match vm.fetch(&"steps".to_owned()).unwrap() {
+                Value::List(ref steps) => assert_eq!(1, steps.borrow().len()),
+                value => panic!("expected a list of steps, got {:?}", value),
+            }
+        }
+    }
+
+    #[test]
+    fn reraise_passes_values_on_to_the_next_handler() {
+        let source = r#"let log = []
+            let pass_on = fn() do
+              reraise
+            end
+            rescue({ "n" => n }) do
+              log = log + [["main", n]]
+            end
+            let f = fn() do
+              rescue(x) do
+                log = log + ["any"]
+                reraise
+              end
+              rescue({ "n" => 1 }) do
+                log = log + ["one"]
+                pass_on()
+              end
+              rescue({ "n" => n }) do
+                log = log + ["n"]
+                reraise({ "n" => n + 1 })
+              end
+              rescue({ "n" => 2 }) do
+                log = log + ["two"]
+                reraise
+              end
+              raise({ "n" => 1 })
+            end
+            f()"#;
+
+        let mut vm = Vm::new(source).unwrap();
+        vm.run();
+        assert_eq!(
+            v_list(vec![
+                v_string("any"),
+                v_string("one"),
+                v_string("n"),
+                v_string("two"),
+                v_list(vec![v_string("main"), v_number(2, 1)]),
+            ]),
+            vm.fetch(&"log".to_owned()).unwrap()
+        );
+    }
+
+    #[test]
+    fn propagate_skips_the_rest_of_the_frame() {
+        let source = r#"let log = []
+            rescue(x) do
+              log = log + [["main", x]]
+            end
+            let f = fn() do
+              rescue("a") do
+                log = log + ["f"]
+                propagate
+              end
+              rescue("b") do
+                log = log + ["never"]
+              end
+              raise("a")
+            end
+            f()"#;
+
+        let mut vm = Vm::new(source).unwrap();
+        vm.run();
+        assert_eq!(
+            v_list(vec![
+                v_string("f"),
+                v_list(vec![v_string("main"), v_string("a")]),
+            ]),
+            vm.fetch(&"log".to_owned()).unwrap()
+        );
+    }
+
+    #[test]
+    fn reraise_outside_of_rescue_blocks_raises_a_rescue_error() {
+        let source = r#"let message = ""
+            rescue({ "error" => "rescue_error", "message" => m }) do
+              message = m
+            end
+            reraise"#;
+
+        let mut vm = Vm::new(source).unwrap();
+        vm.run();
+        assert_eq!(
+            v_string("Only rescue blocks can reraise or propagate"),
+            vm.fetch(&"message".to_owned()).unwrap()
+        );
+    }
+
+    #[test]
+    fn guards_decide_whether_handlers_match() {
+        let source = r#"let log = []
+            let values = [5, 500, 50, 7]
+            rescue({ "i" => 4 }) do
+            end
+            rescue({ "i" => i }) do
+              rescue({ "n" => n }) when n > 100 do
+                log = log + [["big", n]]
+                raise({ "i" => i + 1 })
+              end
+              rescue({ "n" => n }) when n.size > 1 do
+                log = log + ["never"]
+              end
+              rescue({ "n" => n }) when n % 2 do
+                log = log + ["never"]
+              end
+              rescue({ "n" => n }) when n == 7 or n == 5 do
+                log = log + [["odd", n]]
+                raise({ "i" => i + 1 })
+              end
+              rescue({ "n" => n }) do
+                log = log + [["other", n]]
+                raise({ "i" => i + 1 })
+              end
+              raise({ "n" => values[i] })
+            end
+            raise({ "i" => 0 })"#;
+
+        let mut vm = Vm::new(source).unwrap();
+        vm.run();
+        assert_eq!(
+            v_list(vec![
+                v_list(vec![v_string("odd"), v_number(5, 1)]),
+                v_list(vec![v_string("big"), v_number(500, 1)]),
+                v_list(vec![v_string("other"), v_number(50, 1)]),
+                v_list(vec![v_string("odd"), v_number(7, 1)]),
+            ]),
+            vm.fetch(&"log".to_owned()).unwrap()
+        );
+    }
+
+    #[test]
+    fn guard_errors_are_not_raised() {
+        let source = r#"let log = []
+            rescue({ "error" => e }) do
+              log = log + [e]
+            end
+            rescue(n) when [fn() do end, n][1] == 1 and {}["a"] do
+              log = log + ["never"]
+            end
+            rescue(n) when [fn() do end, n][1] == 1 do
+              log = log + [n]
+            end
+            raise(1)"#;
+
+        let mut vm = Vm::new(source).unwrap();
+        vm.set_uncaught_policy(UncaughtPolicy::Strict);
+        vm.run();
+        assert_eq!(
+            v_list(vec![v_number(1, 1)]),
+            vm.fetch(&"log".to_owned()).unwrap()
+        );
+        assert!(vm.uncaught_exceptions().is_empty());
+    }
+
+    #[test]
+    fn guarded_handlers_do_not_shadow_others() {
+        let source = r#"let a = ""
+            let f = fn() do
+              rescue("x") do
+                a = "f"
+              end
+              let g = fn() do
+                rescue("x") when false do
+                end
+                raise("x")
+              end
+              g()
+            end
+            f()"#;
+
+        let mut vm = Vm::new(source).unwrap();
+        vm.set_handler_lookup(HandlerLookup::Global);
+        vm.run();
+
+        assert_eq!(v_string("f"), vm.fetch(&"a".to_owned()).unwrap());
+    }
+
+    #[test]
+    fn pinned_patterns_match_the_current_value_of_bindings() {
+        let source = r#"let replies = []
+            let request = fn(id) do
+              rescue({ "id" => ^id, "reply" => reply }) do
+                replies = replies + [[id, reply]]
+              end
+              raise({ "id" => id + 1, "reply" => "not mine" })
+              raise({ "id" => id, "reply" => "mine" })
+            end
+            request(1)"#;
+
+        let mut vm = Vm::new(source).unwrap();
+        vm.run();
+        assert_eq!(
+            v_list(vec![v_list(vec![v_number(1, 1), v_string("mine")])]),
+            vm.fetch(&"replies".to_owned()).unwrap()
+        );
+    }
+
+    #[test]
+    fn pinned_handlers_do_not_shadow_others() {
+        let source = r#"let a = ""
+            let f = fn(id) do
+              rescue({ "id" => ^id }) do
+                a = "f"
+              end
+              let g = fn(id) do
+                rescue({ "id" => ^id }) do
+                  a = "g"
+                end
+                raise({ "id" => 1 })
+              end
+              g(2)
+            end
+            f(1)"#;
+
+        let mut vm = Vm::new(source).unwrap();
+        vm.set_handler_lookup(HandlerLookup::Global);
+        vm.run();
+
+        assert_eq!(v_string("f"), vm.fetch(&"a".to_owned()).unwrap());
+    }
+
+    // How many frames and handlers raises can still reach.
+    fn reachable_handlers(vm: &Vm) -> (usize, usize) {
+        let frames = Vm::searched_frames(Frame::reachable(&vm.frame));
+        let handlers = frames
+            .iter()
+            .map(|frame| frame.exception_handlers.borrow().len())
+            .sum();
+        (frames.len(), handlers)
+    }
+
+    const FIB: &str = r#"let fib = fn(k) do
+              rescue({ "m" => m, "k" => 0 }) do
+                raise({ "result" => m })
+              end
+              rescue({ "m" => m, "n" => n, "k" => k }) do
+                raise({ "m" => n, "n" => m + n, "k" => k - 1 })
+              end
+              raise({ "m" => 0, "n" => 1, "k" => k })
+            end
+            "#;
+
+    // Run with `cargo test --release -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn benchmark_fib() {
+        let source = FIB.to_owned() + "fib(1000)";
+        let mut vms: Vec<Vm> = (0..100).map(|_| Vm::new(&source).unwrap()).collect();
+        let start = Instant::now();
+        for vm in vms.iter_mut() {
+            vm.run();
+        }
+        let elapsed = start.elapsed();
+        let seconds = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+        let executed: u64 = vms.iter().map(|vm| vm.executed).sum();
+        println!(
+            "fib(1000): {} instructions in {:.3}s, {:.0} instructions/s",
+            executed,
+            seconds,
+            executed as f64 / seconds
+        );
+    }
+
+    #[test]
+    fn raises_reach_the_handlers_of_callers() {
+        let source = r#"let results = []
+            let first = fn(k) do
+              rescue({ "result" => r }) do
+                results = results + [["first", r]]
+                raise({ "next" => r + 1 })
+              end
+              fib(k)
+            end
+            let second = fn(k) do
+              fib(k)
+            end
+            rescue({ "result" => r }) do
+              results = results + [["top", r]]
+            end
+            rescue({ "next" => 6 }) do
+              second(6)
+            end
+            first(5)"#;
+
+        let mut vm = Vm::new(&(FIB.to_owned() + source)).unwrap();
+        vm.run();
+        assert_eq!(
+            v_list(vec![
+                v_list(vec![v_string("first"), v_number(5, 1)]),
+                v_list(vec![v_string("top"), v_number(8, 1)]),
+            ]),
+            vm.fetch(&"results".to_owned()).unwrap()
+        );
+
+        let mut vm = Vm::new(&(FIB.to_owned() + source)).unwrap();
+        vm.set_handler_lookup(HandlerLookup::Global);
+        vm.run();
+        assert_eq!(
+            v_list(vec![
+                v_list(vec![v_string("first"), v_number(5, 1)]),
+                v_list(vec![v_string("first"), v_number(8, 1)]),
+            ]),
+            vm.fetch(&"results".to_owned()).unwrap()
+        );
+    }
+
+    #[test]
+    fn nested_calls_raise_to_the_nearest_caller() {
+        let source = r#"let results = []
+            let sum = fn(a, b) do
+              rescue({ "result" => x }) do
+                rescue({ "result" => y }) do
+                  results = results + [x, y, x + y]
+                end
+                fib(b)
+              end
+              fib(a)
+            end
+            sum(6, 7)"#;
+
+        for lookup in [HandlerLookup::Callers, HandlerLookup::Global] {
+            let mut vm = Vm::new(&(FIB.to_owned() + source)).unwrap();
+            vm.set_handler_lookup(lookup);
+            vm.run();
+            assert_eq!(
+                v_list(vec![v_number(8, 1), v_number(13, 1), v_number(21, 1)]),
+                vm.fetch(&"results".to_owned()).unwrap()
+            );
+        }
+    }
+
+    // Counts the frames and handlers still reachable after a rescue block
+    // registering a handler has raised to itself the given number of times.
+    fn reachable_after_raises(iterations: usize, lookup: HandlerLookup) -> (usize, usize) {
+        let source = format!(
+            r#"let count = 0
+            rescue({{ "i" => {} }}) do
+            end
+            rescue({{ "i" => i }}) do
+              rescue({{ "never" => true }}) do
+              end
+              count = i
+              raise({{ "i" => i + 1 }})
+            end
+            raise({{ "i" => 0 }})"#,
+            iterations
+        );
+
+        let mut vm = Vm::new(&source).unwrap();
+        vm.set_handler_lookup(lookup);
+        vm.run();
+
+        assert_eq!(
+            Value::from_usize(iterations - 1),
+            vm.fetch(&"count".to_owned()).unwrap()
+        );
+        reachable_handlers(&vm)
+    }
+
+    #[test]
+    fn drops_frames_that_cannot_be_reached() {
+        for &lookup in &[HandlerLookup::Callers, HandlerLookup::Global] {
+            let (frames, handlers) = reachable_after_raises(1000, lookup);
+            assert!(frames <= 3);
+            assert!(handlers <= 3);
+            assert_eq!((frames, handlers), reachable_after_raises(2000, lookup));
+        }
+    }
+
+    // Takes most of a minute in debug builds, run it with `cargo test
+    // --release -- --ignored`.
+    #[test]
+    #[ignore]
+    fn runs_a_million_raises_in_bounded_memory() {
+        for &lookup in &[HandlerLookup::Callers, HandlerLookup::Global] {
+            let (frames, handlers) = reachable_after_raises(1000000, lookup);
+            assert!(frames <= 3);
+            assert!(handlers <= 3);
+        }
+    }
+
+    #[test]
+    fn keeps_handlers_that_are_not_shadowed() {
+        let source = r#"let a = ""
+            let f = fn() do
+              rescue("f") do
+                a = "f"
+              end
+              let g = fn() do
+                rescue("g") do
+                end
+                raise("f")
+              end
+              g()
+            end
+            f()"#;
+
+        let mut vm = Vm::new(source).unwrap();
+        vm.run();
+
+        assert_eq!(v_string("f"), vm.fetch(&"a".to_owned()).unwrap());
+    }
+
+    #[test]
+    fn lists() {
+        let source = r#"let a = [1, "two", [3]]
+            a[0] = 0
+            let b = a[1]
+            let c = a[2][0]
+            let d = a + [4]"#;
+
+        let mut vm = Vm::new(source).unwrap();
+        vm.run();
+
+        assert_eq!(
+            v_list(vec![v_number(0, 1), v_string("two"), v_list(vec![v_number(3, 1)])]),
+            vm.fetch(&"a".to_owned()).unwrap()
+        );
+        assert_eq!(v_string("two"), vm.fetch(&"b".to_owned()).unwrap());
+        assert_eq!(v_number(3, 1), vm.fetch(&"c".to_owned()).unwrap());
+        assert_eq!(
+            v_list(vec![
+                v_number(0, 1),
+                v_string("two"),
+                v_list(vec![v_number(3, 1)]),
+                v_number(4, 1),
+            ]),
+            vm.fetch(&"d".to_owned()).unwrap()
+        );
+    }
+
+    #[test]
+    fn multiplying_maps_holding_lists_raises_a_type_error() {
+        let source = r#"let left = ""
+            rescue({"error" => "type_error", "left" => l}) do
+              left = l
+            end
+            let a = {"a" => [1]} * 2"#;
+
+        let mut vm = Vm::new(source).unwrap();
+        vm.run();
+
+        assert_eq!(
+            v_map(vec![(v_string("a"), v_list(vec![v_number(1, 1)]))]),
+            vm.fetch(&"left".to_owned()).unwrap()
+        );
+    }
+
+    #[test]
+    fn rescue_list() {
+        let source = r#"let sum = 0
+            rescue([]) do
+              raise({"sum" => sum})
+            end
+            rescue([head, ..tail]) do
+              sum = sum + head
+              raise(tail)
+            end
+            raise([1, 2, 3, 4])"#;
+
+        let mut vm = Vm::new(source).unwrap();
+        vm.run();
+
+        assert_eq!(v_number(10, 1), vm.fetch(&"sum".to_owned()).unwrap());
+    }
+
     #[test]
     fn fibonacci() {
         let source = r#"let fib = fn(k) do
@@ -523,7 +2056,7 @@ mod test {
         end
         setup()"#;
 
-        let mut vm = Vm::new(source);
+        let mut vm = Vm::new(source).unwrap();
         vm.run();
         assert_eq!(
             v_number(8, 1),
@@ -544,7 +2077,7 @@ mod test {
              end
              a.read("read_test.txt")"#;
 
-        let mut vm = Vm::new(source);
+        let mut vm = Vm::new(source).unwrap();
         vm.run();
         assert_eq!(
             v_string("file content"),